@@ -0,0 +1,413 @@
+use proc_macro::TokenStream;
+use quote::quote;
+use std::collections::{BTreeMap, BTreeSet};
+use syn::spanned::Spanned;
+use syn::{
+    parse_macro_input, Data, DeriveInput, Error, Expr, ExprArray, ExprLit, Fields, Ident, Lit,
+    LitStr, Type,
+};
+
+/// Derive the boilerplate of an enum defining the states of an operation.
+///
+/// The enum has to be tagged by the status of the operation payload:
+///
+/// ```ignore
+/// #[derive(Clone, Debug, Serialize, Deserialize, OperationState)]
+/// #[serde(tag = "status", rename_all = "kebab-case")]
+/// #[operation(operation = "configuration", request = "update")]
+/// pub enum ConfigUpdateState {
+///     #[operation(next = ["scheduled", "failed", "cancelled"])]
+///     Init {
+///         #[serde(flatten)]
+///         request: ConfigUpdateRequest,
+///     },
+///     ...
+/// }
+/// ```
+///
+/// The following methods are generated:
+/// - `status()` returns the status of a state, as used in the operation payload,
+/// - an accessor for each field, returning an `Option` if the field is not defined by all the states,
+/// - the `OperationStates` trait, listing the states and giving the default workflow of the operation.
+///
+/// The default workflow is built from the `operation` and `request` attributes of the enum,
+/// and the `owner` (`"tedge"` by default) and `next` attributes of the variants.
+/// Alternatively, the workflow can be read from a TOML file given by the `workflow` attribute,
+/// a path relative to the crate root, checking at compile time that the enum and the file
+/// agree on the state names.
+#[proc_macro_derive(OperationState, attributes(operation))]
+pub fn derive_operation_state(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match expand(input) {
+        Ok(tokens) => tokens.into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
+/// The attributes of the enum
+#[derive(Default)]
+struct WorkflowAttributes {
+    workflow: Option<LitStr>,
+    operation: Option<String>,
+    request: Option<String>,
+}
+
+/// A state, as defined by an enum variant
+struct StateDefinition {
+    variant: Ident,
+    status: String,
+    owner: String,
+    next: Vec<LitStr>,
+    fields: Vec<(Ident, Type)>,
+}
+
+fn expand(input: DeriveInput) -> Result<proc_macro2::TokenStream, Error> {
+    let name = &input.ident;
+    let Data::Enum(data) = &input.data else {
+        return Err(Error::new(
+            input.span(),
+            "OperationState can only be derived for an enum",
+        ));
+    };
+
+    check_serde_tag(&input)?;
+    let attributes = workflow_attributes(&input)?;
+
+    let mut states = vec![];
+    for variant in &data.variants {
+        states.push(state_definition(variant)?);
+    }
+    let statuses: BTreeSet<String> = states.iter().map(|s| s.status.clone()).collect();
+    for state in &states {
+        for next in &state.next {
+            if !statuses.contains(&next.value()) {
+                return Err(Error::new(
+                    next.span(),
+                    format!("Unknown state: {}", next.value()),
+                ));
+            }
+        }
+    }
+
+    let status_arms = states.iter().map(|state| {
+        let variant = &state.variant;
+        let status = &state.status;
+        quote! { #name::#variant { .. } => #status }
+    });
+    let all_statuses = states.iter().map(|state| &state.status);
+    let accessors = field_accessors(name, &states)?;
+    let workflow = match &attributes.workflow {
+        Some(path) => bundled_workflow(path, &statuses)?,
+        None => workflow_skeleton(&attributes, &states),
+    };
+
+    Ok(quote! {
+        impl #name {
+            /// The status of this state, as used in the operation payload
+            pub fn status(&self) -> &'static str {
+                match self {
+                    #(#status_arms),*
+                }
+            }
+
+            #(#accessors)*
+        }
+
+        impl crate::operations_sm::plugin::OperationStates for #name {
+            const STATES: &'static [&'static str] = &[#(#all_statuses),*];
+
+            fn workflow() -> crate::operations_sm::config::OperationWorkflow {
+                #workflow
+            }
+        }
+    })
+}
+
+/// Check that the states are tagged by status, as expected for an operation payload
+fn check_serde_tag(input: &DeriveInput) -> Result<(), Error> {
+    let mut tag = None;
+    let mut rename_all = None;
+    for attr in input.attrs.iter().filter(|a| a.path().is_ident("serde")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("tag") {
+                tag = Some(meta.value()?.parse::<LitStr>()?.value());
+            } else if meta.path.is_ident("rename_all") {
+                rename_all = Some(meta.value()?.parse::<LitStr>()?.value());
+            } else if meta.input.peek(syn::Token![=]) {
+                meta.value()?.parse::<Expr>()?;
+            }
+            Ok(())
+        })?;
+    }
+    if tag.as_deref() != Some("status") || rename_all.as_deref() != Some("kebab-case") {
+        return Err(Error::new(
+            input.ident.span(),
+            "The states of an operation have to be tagged with #[serde(tag = \"status\", rename_all = \"kebab-case\")]",
+        ));
+    }
+    Ok(())
+}
+
+fn workflow_attributes(input: &DeriveInput) -> Result<WorkflowAttributes, Error> {
+    let mut attributes = WorkflowAttributes::default();
+    for attr in input
+        .attrs
+        .iter()
+        .filter(|a| a.path().is_ident("operation"))
+    {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("workflow") {
+                attributes.workflow = Some(meta.value()?.parse()?);
+            } else if meta.path.is_ident("operation") {
+                attributes.operation = Some(meta.value()?.parse::<LitStr>()?.value());
+            } else if meta.path.is_ident("request") {
+                attributes.request = Some(meta.value()?.parse::<LitStr>()?.value());
+            } else {
+                return Err(meta.error("Expected `workflow`, `operation` or `request`"));
+            }
+            Ok(())
+        })?;
+    }
+    Ok(attributes)
+}
+
+fn state_definition(variant: &syn::Variant) -> Result<StateDefinition, Error> {
+    let mut status = kebab_case(&variant.ident.to_string());
+    let mut owner = "tedge".to_string();
+    let mut next = vec![];
+
+    for attr in &variant.attrs {
+        if attr.path().is_ident("serde") {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("rename") {
+                    status = meta.value()?.parse::<LitStr>()?.value();
+                } else if meta.input.peek(syn::Token![=]) {
+                    meta.value()?.parse::<Expr>()?;
+                }
+                Ok(())
+            })?;
+        } else if attr.path().is_ident("operation") {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("owner") {
+                    owner = meta.value()?.parse::<LitStr>()?.value();
+                } else if meta.path.is_ident("next") {
+                    let states: ExprArray = meta.value()?.parse()?;
+                    for state in states.elems {
+                        match state {
+                            Expr::Lit(ExprLit {
+                                lit: Lit::Str(state),
+                                ..
+                            }) => next.push(state),
+                            _ => return Err(Error::new(state.span(), "Expected a state name")),
+                        }
+                    }
+                } else {
+                    return Err(meta.error("Expected `owner` or `next`"));
+                }
+                Ok(())
+            })?;
+        }
+    }
+
+    let fields = match &variant.fields {
+        Fields::Named(fields) => fields
+            .named
+            .iter()
+            .map(|f| (f.ident.clone().unwrap(), f.ty.clone()))
+            .collect(),
+        Fields::Unit => vec![],
+        Fields::Unnamed(_) => {
+            return Err(Error::new(
+                variant.span(),
+                "The fields of an operation state have to be named",
+            ))
+        }
+    };
+
+    Ok(StateDefinition {
+        variant: variant.ident.clone(),
+        status,
+        owner,
+        next,
+        fields,
+    })
+}
+
+/// Generate an accessor per field name,
+/// returning an `Option` for the fields that are not defined by all the states
+fn field_accessors(
+    name: &Ident,
+    states: &[StateDefinition],
+) -> Result<Vec<proc_macro2::TokenStream>, Error> {
+    let mut fields: BTreeMap<String, (Ident, Type, Vec<&Ident>)> = BTreeMap::new();
+    for state in states {
+        for (field, ty) in &state.fields {
+            let (_, field_type, variants) = fields
+                .entry(field.to_string())
+                .or_insert_with(|| (field.clone(), ty.clone(), vec![]));
+            if quote!(#field_type).to_string() != quote!(#ty).to_string() {
+                return Err(Error::new(
+                    ty.span(),
+                    format!("The field `{field}` has to be of the same type for all the states"),
+                ));
+            }
+            variants.push(&state.variant);
+        }
+    }
+
+    Ok(fields
+        .into_values()
+        .map(|(field, ty, variants)| {
+            let doc = format!("The `{field}` of this state");
+            if variants.len() == states.len() {
+                quote! {
+                    #[doc = #doc]
+                    pub fn #field(&self) -> &#ty {
+                        match self {
+                            #(#name::#variants { #field, .. })|* => #field,
+                        }
+                    }
+                }
+            } else {
+                quote! {
+                    #[doc = #doc]
+                    pub fn #field(&self) -> Option<&#ty> {
+                        match self {
+                            #(#name::#variants { #field, .. })|* => Some(#field),
+                            _ => None,
+                        }
+                    }
+                }
+            }
+        })
+        .collect())
+}
+
+/// Read the workflow bundled with a plugin, checking its states are those of the enum
+fn bundled_workflow(
+    path: &LitStr,
+    statuses: &BTreeSet<String>,
+) -> Result<proc_macro2::TokenStream, Error> {
+    let root = std::env::var("CARGO_MANIFEST_DIR").unwrap_or_default();
+    let file = std::path::Path::new(&root).join(path.value());
+    let content = std::fs::read_to_string(&file).map_err(|err| {
+        Error::new(
+            path.span(),
+            format!("Fail to read {}: {err}", file.display()),
+        )
+    })?;
+    let workflow: toml::Table = toml::from_str(&content).map_err(|err| {
+        Error::new(
+            path.span(),
+            format!("Invalid workflow {}: {err}", file.display()),
+        )
+    })?;
+
+    let states: BTreeSet<String> = workflow
+        .iter()
+        .filter(|(_, v)| v.is_table())
+        .map(|(k, _)| k.clone())
+        .collect();
+    let missing: Vec<&String> = statuses.difference(&states).collect();
+    let unknown: Vec<&String> = states.difference(statuses).collect();
+    if !missing.is_empty() || !unknown.is_empty() {
+        return Err(Error::new(
+            path.span(),
+            format!(
+                "The states of {} don't match the enum: missing {missing:?}, unknown {unknown:?}",
+                path.value()
+            ),
+        ));
+    }
+
+    let path = path.value();
+    Ok(quote! {
+        toml::from_str(include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/", #path))).unwrap()
+    })
+}
+
+/// Build a workflow from the attributes of the enum and its variants
+fn workflow_skeleton(
+    attributes: &WorkflowAttributes,
+    states: &[StateDefinition],
+) -> proc_macro2::TokenStream {
+    let operation = option_tokens(&attributes.operation);
+    let request = option_tokens(&attributes.request);
+    let states = states.iter().map(|state| {
+        let status = &state.status;
+        let owner = &state.owner;
+        let next = &state.next;
+        quote! {
+            workflow.states.insert(
+                #status.to_string(),
+                crate::operations_sm::config::OperationState {
+                    owner: #owner.to_string(),
+                    next: vec![#(#next.to_string()),*],
+                    ..Default::default()
+                },
+            );
+        }
+    });
+    quote! {
+        let mut workflow = crate::operations_sm::config::OperationWorkflow::default();
+        workflow.filter.operation = #operation;
+        workflow.filter.request = #request;
+        #(#states)*
+        workflow
+    }
+}
+
+fn option_tokens(value: &Option<String>) -> proc_macro2::TokenStream {
+    match value {
+        Some(value) => quote! { Some(#value.to_string()) },
+        None => quote! { None },
+    }
+}
+
+/// `RolledBack` -> `rolled-back`, exactly as done by serde for `rename_all = "kebab-case"`
+///
+/// Hence, each uppercase letter but the first starts a new word: `HTTPError` -> `h-t-t-p-error`.
+fn kebab_case(name: &str) -> String {
+    let mut snake = String::new();
+    for (i, ch) in name.char_indices() {
+        if i > 0 && ch.is_uppercase() {
+            snake.push('_');
+        }
+        snake.push(ch.to_ascii_lowercase());
+    }
+    snake.replace('_', "-")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Serialize;
+
+    #[allow(non_camel_case_types, clippy::upper_case_acronyms)]
+    #[derive(Serialize)]
+    #[serde(rename_all = "kebab-case")]
+    enum Status {
+        Init,
+        RolledBack,
+        HTTPError,
+        Rolled_Back,
+        ÉtatInitial,
+        V2Update,
+    }
+
+    #[test]
+    fn kebab_case_as_serde() {
+        for (name, status) in [
+            ("Init", Status::Init),
+            ("RolledBack", Status::RolledBack),
+            ("HTTPError", Status::HTTPError),
+            ("Rolled_Back", Status::Rolled_Back),
+            ("ÉtatInitial", Status::ÉtatInitial),
+            ("V2Update", Status::V2Update),
+        ] {
+            let serde_name = serde_json::to_value(status).unwrap();
+            assert_eq!(kebab_case(name), serde_name.as_str().unwrap());
+        }
+        assert_eq!(kebab_case("HTTPError"), "h-t-t-p-error");
+    }
+}
@@ -0,0 +1,22 @@
+use std::path::Path;
+
+#[test]
+fn compile_fail() {
+    // The workflow files are read relative to the manifest directory of the crate using the macro,
+    // i.e., for these tests, relative to the project generated by trybuild.
+    let target_dir = Path::new(env!("CARGO_TARGET_TMPDIR")).parent().unwrap();
+    let project_dir = target_dir
+        .join("tests/trybuild")
+        .join(env!("CARGO_PKG_NAME"))
+        .join("tests/ui");
+    std::fs::create_dir_all(&project_dir).unwrap();
+    for entry in std::fs::read_dir("tests/ui").unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().is_some_and(|ext| ext == "toml") {
+            std::fs::copy(&path, project_dir.join(path.file_name().unwrap())).unwrap();
+        }
+    }
+
+    let tests = trybuild::TestCases::new();
+    tests.compile_fail("tests/ui/*.rs");
+}
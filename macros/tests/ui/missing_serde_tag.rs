@@ -0,0 +1,12 @@
+use serde::{Deserialize, Serialize};
+use tedge_mqtt_state_machine_macros::OperationState;
+
+#[derive(Serialize, Deserialize, OperationState)]
+#[serde(rename_all = "kebab-case")]
+#[operation(operation = "demo", request = "update")]
+pub enum DemoState {
+    Init {},
+    Successful {},
+}
+
+fn main() {}
@@ -0,0 +1,14 @@
+use serde::{Deserialize, Serialize};
+use tedge_mqtt_state_machine_macros::OperationState;
+
+#[derive(Serialize, Deserialize, OperationState)]
+#[serde(tag = "status", rename_all = "kebab-case")]
+#[operation(operation = "demo", request = "update")]
+pub enum DemoState {
+    #[operation(next = ["scheduled", "successful"])]
+    Init {},
+    #[operation(next = [])]
+    Successful {},
+}
+
+fn main() {}
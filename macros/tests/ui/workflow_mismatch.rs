@@ -0,0 +1,14 @@
+use serde::{Deserialize, Serialize};
+use tedge_mqtt_state_machine_macros::OperationState;
+
+#[derive(Serialize, Deserialize, OperationState)]
+#[serde(tag = "status", rename_all = "kebab-case")]
+#[operation(workflow = "tests/ui/workflow_mismatch.toml")]
+pub enum DemoState {
+    Init {},
+    Scheduled {},
+    Successful {},
+    Failed {},
+}
+
+fn main() {}
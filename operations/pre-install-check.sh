@@ -1,3 +1,16 @@
 #!/bin/sh
+# Check the downloaded configuration before its installation.
+#
+# The current state of the operation is given as the last argument,
+# and the new state is printed on stdout, with all the fields of the current one.
 
-echo '{ "status":"installing", "target":"mosquito", "src_url":"here", "sha256":"okay" }'
+state="$1"
+
+sha256=$(echo "$state" | sed -n 's/.*"sha256":"\([0-9a-fA-F]*\)".*/\1/p' | tr 'A-F' 'a-f')
+checksum=$(echo "$state" | sed -n 's/.*"checksum":"\([0-9a-f]*\)".*/\1/p')
+
+if [ -n "$sha256" ] && [ "$sha256" != "$checksum" ]; then
+    echo "$state" | sed 's/"status":"downloaded"/"status":"failed","reason":"Checksum mismatch"/'
+else
+    echo "$state" | sed 's/"status":"downloaded"/"status":"installing"/'
+fi
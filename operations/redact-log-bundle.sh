@@ -0,0 +1,17 @@
+#!/bin/sh
+# Redact the collected log bundle before its upload.
+#
+# The current state of the operation is given as the last argument,
+# and the new state is printed on stdout, with all the fields of the current one.
+
+state="$1"
+
+path=$(echo "$state" | sed -n 's/.*"path":"\([^"]*\)".*/\1/p')
+
+if ! sed -i 's/\(password\|token\)=[^ ]*/\1=*****/g' "$path"; then
+    echo "$state" | sed 's/"status":"collected"/"status":"failed","reason":"Fail to redact the log bundle"/'
+    exit 0
+fi
+
+size=$(wc -c < "$path")
+echo "$state" | sed -e 's/"status":"collected"/"status":"uploading"/' -e "s/\"size\":[0-9]*/\"size\":$size/"
@@ -0,0 +1,12 @@
+#!/bin/sh
+
+# Install a firmware, given the path of the downloaded image.
+#
+# This demo script simply stages the firmware image, along the current boot id,
+# to be activated on the next boot of the device.
+
+set -e
+
+mkdir -p /var/lib/firmware-demo
+cp "$1" /var/lib/firmware-demo/pending.img
+cat /proc/sys/kernel/random/boot_id > /var/lib/firmware-demo/pending.boot_id
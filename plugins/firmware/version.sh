@@ -0,0 +1,19 @@
+#!/bin/sh
+
+# Print the version of the running firmware.
+#
+# This demo script reads the version from the first line of the running firmware image.
+# Playing the role of a bootloader, a staged image is only activated
+# once the device has been restarted since its installation.
+
+set -e
+
+DIR=/var/lib/firmware-demo
+
+if [ -f "$DIR/pending.img" ] && ! cmp -s "$DIR/pending.boot_id" /proc/sys/kernel/random/boot_id
+then
+    mv "$DIR/pending.img" "$DIR/running.img"
+    rm -f "$DIR/pending.boot_id"
+fi
+
+head -n 1 "$DIR/running.img"
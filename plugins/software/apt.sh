@@ -0,0 +1,30 @@
+#!/bin/sh
+
+# A package manager for the software plugin, based on apt.
+#
+# Usage:
+#   apt.sh install <name> [<version>]
+#   apt.sh remove <name> [<version>]
+#   apt.sh list
+
+set -e
+
+case "$1" in
+    install)
+        if [ -n "$3" ]; then
+            apt-get install --quiet --yes "$2=$3" >&2
+        else
+            apt-get install --quiet --yes "$2" >&2
+        fi
+        ;;
+    remove)
+        apt-get remove --quiet --yes "$2" >&2
+        ;;
+    list)
+        dpkg-query --show --showformat='${Package}\t${Version}\n'
+        ;;
+    *)
+        echo "Unknown command: $1" >&2
+        exit 1
+        ;;
+esac
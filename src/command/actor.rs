@@ -0,0 +1,319 @@
+use crate::command::config::CommandPluginConfig;
+use crate::command::messages::{CommandExecuteState, CommandOutput, CommandRequest};
+use crate::operations_sm::config::OperationKey;
+use crate::operations_sm::plugin::{OperationPlugin, OperationPluginTasks};
+use crate::operations_sm::script::execute;
+use std::os::unix::process::ExitStatusExt;
+use std::process::ExitStatus;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tedge_script_ext::Execute;
+use tokio::sync::Semaphore;
+
+/// The exit code of the `timeout` command when the command timed out
+const TIMEOUT_EXIT_CODE: i32 = 124;
+
+/// The exit code of the `timeout` command when the command had to be killed after the timeout
+const KILLED_EXIT_CODE: i32 = 128 + 9;
+
+/// Plugin that handles the remote command requests.
+///
+/// The commands are executed one at a time,
+/// after being checked against the plugin policy.
+pub struct CommandManager {
+    config: CommandPluginConfig,
+
+    /// The permit to execute a command, the other commands waiting in the background for their turn
+    executing: Arc<Semaphore>,
+}
+
+impl OperationPlugin for CommandManager {
+    type State = CommandExecuteState;
+
+    const HANDLED_STATES: &'static [&'static str] = &["init", "executing"];
+
+    fn name(&self) -> &str {
+        "CommandManager"
+    }
+
+    fn update(
+        &mut self,
+        operation: &OperationKey,
+        state: CommandExecuteState,
+        tasks: &mut OperationPluginTasks<CommandExecuteState>,
+    ) -> Option<CommandExecuteState> {
+        match state {
+            CommandExecuteState::Init { request } => Some(self.init(request)),
+            CommandExecuteState::Executing { request } => {
+                self.start_execution(operation, request, tasks)
+            }
+            CommandExecuteState::Successful { .. }
+            | CommandExecuteState::Failed { .. }
+            | CommandExecuteState::Cancelled { .. } => None,
+        }
+    }
+
+    /// Resume an operation found on start in a state owned by this plugin.
+    ///
+    /// A command interrupted by a restart is not executed again,
+    /// as there is no way to know what has been done before the interruption.
+    fn recover(
+        &mut self,
+        operation: &OperationKey,
+        state: CommandExecuteState,
+        tasks: &mut OperationPluginTasks<CommandExecuteState>,
+    ) -> Option<CommandExecuteState> {
+        match state {
+            CommandExecuteState::Executing { request } if !tasks.is_pending(operation) => {
+                Some(CommandExecuteState::Failed {
+                    request,
+                    reason: "The command has been interrupted by a restart".to_string(),
+                    output: None,
+                })
+            }
+            state => self.update(operation, state, tasks),
+        }
+    }
+}
+
+impl CommandManager {
+    pub fn new(config: CommandPluginConfig) -> Self {
+        CommandManager {
+            config,
+            executing: Arc::new(Semaphore::new(1)),
+        }
+    }
+
+    /// A new request is immediately executed, unless denied by the policy.
+    fn init(&mut self, request: CommandRequest) -> CommandExecuteState {
+        match self.check(&request) {
+            Ok(_) => CommandExecuteState::Executing { request },
+            Err(reason) => CommandExecuteState::Failed {
+                request,
+                reason,
+                output: None,
+            },
+        }
+    }
+
+    /// Check the command against the policy, returning the command to execute and its timeout in seconds.
+    ///
+    /// This check is done again on execution,
+    /// as a custom workflow might have replaced the `init` step.
+    fn check(&self, request: &CommandRequest) -> Result<(Execute, u64), String> {
+        let command_line = &request.command;
+        let command = Execute::try_new(command_line)
+            .map_err(|err| format!("Fail to parse the command line {command_line}: {err}"))?;
+        if !self.config.is_allowed(&command) {
+            return Err(format!("The command {command_line} is not allowed"));
+        }
+        Ok((command, self.timeout(request)?))
+    }
+
+    /// The timeout of a command, bounded by the plugin policy.
+    ///
+    /// A zero timeout is rejected, as `timeout 0` would let the command run forever.
+    fn timeout(&self, request: &CommandRequest) -> Result<u64, String> {
+        let max_timeout = self.config.timeout.max(1);
+        match request.timeout {
+            Some(0) => Err("The command timeout must be at least 1 second".to_string()),
+            Some(timeout) => Ok(timeout.min(max_timeout)),
+            None => Ok(max_timeout),
+        }
+    }
+
+    /// Execute the command in the background, killing it if not completed before the timeout
+    fn start_execution(
+        &mut self,
+        operation: &OperationKey,
+        request: CommandRequest,
+        tasks: &mut OperationPluginTasks<CommandExecuteState>,
+    ) -> Option<CommandExecuteState> {
+        if tasks.is_pending(operation) {
+            return None;
+        }
+        let (command, timeout) = match self.check(&request) {
+            Ok(checked) => checked,
+            Err(reason) => {
+                return Some(CommandExecuteState::Failed {
+                    request,
+                    reason,
+                    output: None,
+                })
+            }
+        };
+
+        let mut args = vec![
+            "--kill-after=5".to_string(),
+            timeout.to_string(),
+            command.command,
+        ];
+        args.extend(command.args);
+        let command = Execute {
+            command: "timeout".to_string(),
+            args,
+        };
+
+        let id = String::from(operation);
+        let max_output = self.config.max_output;
+        let executing = self.executing.clone();
+        tasks.spawn(operation, async move {
+            let _permit = match executing.acquire_owned().await {
+                Ok(permit) => permit,
+                Err(err) => {
+                    return Some(CommandExecuteState::Failed {
+                        request,
+                        reason: format!("Fail to schedule the command: {err}"),
+                        output: None,
+                    })
+                }
+            };
+
+            log::info!("Execute {} for {id}", request.command);
+            let started = Instant::now();
+            let output = match execute(&command).await {
+                Ok(output) => output,
+                Err(err) => {
+                    let reason = format!("Fail to launch {}: {err}", request.command);
+                    return Some(CommandExecuteState::Failed {
+                        request,
+                        reason,
+                        output: None,
+                    });
+                }
+            };
+
+            let status = output.status;
+            let output = CommandOutput {
+                exit_code: status.code(),
+                stdout: truncate(&output.stdout, max_output),
+                stderr: truncate(&output.stderr, max_output),
+            };
+            let reason = if status.success() {
+                return Some(CommandExecuteState::Successful {
+                    request,
+                    output: Some(output),
+                });
+            } else if timed_out(status, started.elapsed(), timeout) {
+                format!("The command timed out after {timeout} seconds")
+            } else {
+                format!("The command failed with {status}")
+            };
+            Some(CommandExecuteState::Failed {
+                request,
+                reason,
+                output: Some(output),
+            })
+        });
+        None
+    }
+}
+
+/// Check if a command has been stopped by `timeout`
+///
+/// The command might have been terminated on time, or killed later (`--kill-after`),
+/// and `timeout` itself might have been killed by a signal.
+/// These statuses are only due to `timeout` if the command ran until the timeout:
+/// before, they are the exit code of the command or a signal sent by someone else.
+fn timed_out(status: ExitStatus, elapsed: Duration, timeout: u64) -> bool {
+    let stopped = matches!(
+        status.code(),
+        Some(TIMEOUT_EXIT_CODE) | Some(KILLED_EXIT_CODE)
+    ) || status.signal().is_some();
+    stopped && elapsed >= Duration::from_secs(timeout)
+}
+
+/// Convert a command output into a string of at most `max` bytes
+fn truncate(output: &[u8], max: usize) -> String {
+    let output = String::from_utf8_lossy(output);
+    if output.len() <= max {
+        return output.to_string();
+    }
+    let mut end = max;
+    while !output.is_char_boundary(end) {
+        end -= 1;
+    }
+    output[..end].to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detect_the_commands_stopped_by_timeout() {
+        let exit = |code: i32| ExitStatus::from_raw(code << 8);
+        let signal = |signal: i32| ExitStatus::from_raw(signal);
+        let on_time = Duration::from_secs(10);
+
+        assert!(!timed_out(exit(0), on_time, 10));
+        assert!(!timed_out(exit(1), on_time, 10));
+        assert!(timed_out(exit(124), on_time, 10));
+        assert!(timed_out(exit(137), on_time, 10));
+        assert!(timed_out(signal(9), on_time, 10));
+        assert!(timed_out(signal(15), on_time, 10));
+    }
+
+    #[test]
+    fn a_command_stopped_before_the_timeout_is_not_timed_out() {
+        let exit = |code: i32| ExitStatus::from_raw(code << 8);
+        let signal = |signal: i32| ExitStatus::from_raw(signal);
+        let too_early = Duration::from_secs(3);
+
+        assert!(!timed_out(exit(124), too_early, 10));
+        assert!(!timed_out(exit(137), too_early, 10));
+        assert!(!timed_out(signal(15), too_early, 10));
+    }
+
+    #[test]
+    fn a_command_timeout_is_at_least_one_second() {
+        let config = CommandPluginConfig {
+            allow: vec!["uptime".to_string()],
+            ..Default::default()
+        };
+        let manager = CommandManager::new(config.clone());
+        let request = |timeout| CommandRequest {
+            command: "uptime".to_string(),
+            timeout,
+        };
+
+        assert_eq!(manager.timeout(&request(None)), Ok(60));
+        assert_eq!(manager.timeout(&request(Some(10))), Ok(10));
+        assert_eq!(manager.timeout(&request(Some(600))), Ok(60));
+        assert!(manager.timeout(&request(Some(0))).is_err());
+        assert!(matches!(
+            CommandManager::new(config.clone()).init(request(Some(0))),
+            CommandExecuteState::Failed { .. }
+        ));
+
+        let manager = CommandManager::new(CommandPluginConfig {
+            timeout: 0,
+            ..config
+        });
+        assert_eq!(manager.timeout(&request(None)), Ok(1));
+        assert_eq!(manager.timeout(&request(Some(10))), Ok(1));
+    }
+
+    #[tokio::test]
+    async fn a_command_killed_after_the_timeout_is_timed_out() {
+        let command = Execute {
+            command: "timeout".to_string(),
+            args: vec![
+                "--signal=CONT".to_string(),
+                "--kill-after=1".to_string(),
+                "1".to_string(),
+                "sleep".to_string(),
+                "10".to_string(),
+            ],
+        };
+
+        // GNU timeout kills itself along the command, while other implementations exit with 137
+        let started = Instant::now();
+        let output = execute(&command).await.unwrap();
+        assert!(matches!(
+            (output.status.code(), output.status.signal()),
+            (None, Some(9)) | (Some(KILLED_EXIT_CODE), None)
+        ));
+        assert!(timed_out(output.status, started.elapsed(), 1));
+    }
+}
@@ -0,0 +1,65 @@
+use crate::operations_sm::plugin::PluginConfig;
+use serde::{Deserialize, Serialize};
+use tedge_script_ext::Execute;
+
+/// The policy of the command plugin
+///
+/// Only the commands starting with the words of an allowed command line can be executed,
+/// and no command can run longer than the timeout.
+///
+/// ```toml
+/// timeout = 60
+/// allow = [
+///     "uptime",
+///     "df -h",
+///     "systemctl status",
+/// ]
+/// ```
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CommandPluginConfig {
+    /// The maximum duration of a command, in seconds, at least 1 second
+    #[serde(default = "default_timeout")]
+    pub timeout: u64,
+
+    /// The maximum size, in bytes, of the stdout and stderr reported for a command
+    #[serde(default = "default_max_output")]
+    pub max_output: usize,
+
+    /// The allowed command lines
+    #[serde(default)]
+    pub allow: Vec<String>,
+}
+
+impl Default for CommandPluginConfig {
+    fn default() -> Self {
+        CommandPluginConfig {
+            timeout: default_timeout(),
+            max_output: default_max_output(),
+            allow: vec![],
+        }
+    }
+}
+
+fn default_timeout() -> u64 {
+    60
+}
+
+fn default_max_output() -> usize {
+    16 * 1024
+}
+
+impl CommandPluginConfig {
+    /// Check that a command is allowed by the policy
+    pub fn is_allowed(&self, command: &Execute) -> bool {
+        let words: Vec<&str> = std::iter::once(command.command.as_str())
+            .chain(command.args.iter().map(String::as_str))
+            .collect();
+        self.allow.iter().any(|allowed| {
+            let allowed: Vec<&str> = allowed.split_whitespace().collect();
+            !allowed.is_empty() && words.starts_with(&allowed)
+        })
+    }
+}
+
+/// With no policy file, all the commands are denied.
+impl PluginConfig for CommandPluginConfig {}
@@ -0,0 +1,71 @@
+use serde::Deserialize;
+use serde::Serialize;
+use tedge_mqtt_state_machine_macros::OperationState;
+
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct CommandRequest {
+    /// The command line to execute
+    pub command: String,
+
+    /// The maximum duration of the command, in seconds, bounded by the plugin policy
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout: Option<u64>,
+}
+
+/// The outcome of a command
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct CommandOutput {
+    /// The exit code of the command, if not killed by a signal
+    pub exit_code: Option<i32>,
+
+    /// The captured stdout, possibly truncated
+    pub stdout: String,
+
+    /// The captured stderr, possibly truncated
+    pub stderr: String,
+}
+
+/// The states of a remote command request
+///
+/// The default workflow of the operation is derived from the `next` attributes of the states.
+/// The output is only missing for a command that has not been executed.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize, OperationState)]
+#[serde(tag = "status", rename_all = "kebab-case")]
+#[operation(operation = "command", request = "execute")]
+pub enum CommandExecuteState {
+    /// The command is checked against the policy of the plugin before being executed.
+    #[operation(next = ["executing", "failed", "cancelled"])]
+    Init {
+        #[serde(flatten)]
+        request: CommandRequest,
+    },
+    /// A running command can not be cancelled, but is killed on timeout.
+    #[operation(next = ["successful", "failed"])]
+    Executing {
+        #[serde(flatten)]
+        request: CommandRequest,
+    },
+    #[operation(next = [])]
+    Successful {
+        #[serde(flatten)]
+        request: CommandRequest,
+        #[serde(flatten)]
+        output: Option<CommandOutput>,
+    },
+    #[operation(next = [])]
+    Failed {
+        #[serde(flatten)]
+        request: CommandRequest,
+        #[serde(default)]
+        reason: String,
+        #[serde(flatten)]
+        output: Option<CommandOutput>,
+    },
+    #[operation(next = [])]
+    Cancelled {
+        #[serde(flatten)]
+        request: CommandRequest,
+        #[serde(default)]
+        reason: String,
+    },
+}
@@ -1,158 +1,481 @@
-use crate::configuration::messages::{ConfigUpdateRequest, ConfigUpdateRequestState};
-use async_trait::async_trait;
-use std::time::Duration;
-use tedge_actors::futures::stream::FuturesUnordered;
-use tedge_actors::futures::StreamExt;
-use tedge_actors::{Actor, MessageReceiver, RuntimeError, Sender, SimpleMessageBox};
-use tokio::task::JoinHandle;
-
-/// Using fake download for this POC.
+use crate::configuration::config::{ConfigPluginConfig, ConfigTarget};
+use crate::configuration::download::{download, sha256_digest};
+use crate::configuration::file_transfer::FileTransferService;
+use crate::configuration::install::{install, InstallError};
+use crate::configuration::messages::{ConfigUpdateRequest, ConfigUpdateState};
+use crate::operations_sm::config::{OperationKey, OperationState, OperationWorkflow};
+use crate::operations_sm::plugin::{OperationPlugin, OperationPluginTasks};
+
+/// A download in progress
 struct Download {
-    id: String,
     request: ConfigUpdateRequest,
     path: String,
 }
 
 impl Download {
-    fn new(id: &str, request: &ConfigUpdateRequest, path: &str) -> Self {
+    fn new(request: &ConfigUpdateRequest, path: &str) -> Self {
         Download {
-            id: id.to_string(),
             request: request.clone(),
             path: path.to_string(),
         }
     }
 
-    async fn execute(self) -> Self {
-        tokio::time::sleep(Duration::from_secs(3)).await;
-        self
+    async fn execute(self) -> ConfigUpdateState {
+        match self.download_and_digest().await {
+            Ok(checksum) => ConfigUpdateState::Downloaded {
+                request: self.request,
+                path: self.path,
+                checksum,
+            },
+            Err(reason) => ConfigUpdateState::Failed {
+                request: self.request,
+                reason,
+            },
+        }
+    }
+
+    /// Download the file, returning its actual sha256 digest.
+    ///
+    /// This digest is checked against the one given by the request on the `downloaded` step.
+    async fn download_and_digest(&self) -> Result<String, String> {
+        download(&self.request.src_url, &self.path).await?;
+        sha256_digest(&self.path).await
+    }
+}
+
+/// Check the digest of a downloaded file against the one given by the request, if any.
+///
+/// The file is removed on mismatch.
+async fn verify(request: &ConfigUpdateRequest, path: &str, checksum: String) -> Result<(), String> {
+    let expected = &request.sha256;
+    if expected.is_empty() {
+        return Ok(());
+    }
+    let checksum = match checksum {
+        checksum if !checksum.is_empty() => checksum,
+        _ => sha256_digest(path).await?,
+    };
+    if !expected.eq_ignore_ascii_case(&checksum) {
+        let _ = tokio::fs::remove_file(path).await;
+        return Err(format!(
+            "Checksum mismatch for {}: expected sha256 {expected}, got {checksum}",
+            request.src_url
+        ));
     }
+    Ok(())
 }
 
-/// Actor that handles the configuration update requests.
+/// The subsystem of the operations that target the gateway itself
+const MAIN_DEVICE: &str = "main-device";
+
+/// The delay, in seconds, given by default to a child device to install a new configuration
+const CHILD_INSTALL_TIMEOUT: u64 = 300;
+
+/// Plugin that handles the configuration update requests.
+///
+/// For a child device, the new configuration is downloaded by the gateway
+/// and served to the child by the file transfer service,
+/// the child being responsible for the installation.
 pub struct ConfigManager {
-    message_box: SimpleMessageBox<ConfigUpdateRequestState, ConfigUpdateRequestState>,
-    downloads: FuturesUnordered<JoinHandle<Download>>,
+    /// The configuration files managed by this plugin
+    config: ConfigPluginConfig,
+
+    /// The file transfer service, once started
+    file_transfer: Option<FileTransferService>,
 }
 
-#[async_trait]
-impl Actor for ConfigManager {
+impl OperationPlugin for ConfigManager {
+    type State = ConfigUpdateState;
+
+    const HANDLED_STATES: &'static [&'static str] = &[
+        "init",
+        "scheduled",
+        "downloading",
+        "downloaded",
+        "installing",
+    ];
+
     fn name(&self) -> &str {
-        ConfigManager::name()
-    }
-
-    async fn run(&mut self) -> Result<(), RuntimeError> {
-        loop {
-            let maybe_response = tokio::select! {
-                Some(request) = self.message_box.recv() => {
-                    match request {
-                        ConfigUpdateRequestState::Init { id, request } => {
-                            Some(self.init(id, request))
-                        }
-                        ConfigUpdateRequestState::Scheduled { id, request } => {
-                            Some(self.start_download(id, request))
-                        }
-                        ConfigUpdateRequestState::Downloading { .. } => {
-                            // This event is only useful for the other participants
-                            // while this actor awaits for the actual end of the download
-                            None
-                        }
-                        ConfigUpdateRequestState::Downloaded { id, request, path } => {
-                            Some(self.start_install(id, request, path))
-                        }
-                        ConfigUpdateRequestState::Installing { id, request, path } => {
-                            Some(self.install(id, request, path))
-                        }
-                        ConfigUpdateRequestState::Successful { .. } => {
-                            // This event is only useful for the other participants
-                            None
-                        }
-                        ConfigUpdateRequestState::Failed { .. } => {
-                            // This event is only useful for the other participants
-                            None
-                        }
-                        ConfigUpdateRequestState::InvalidState { id, error } => {
-                            log::error!("Can not process invalid state {id} : {error}");
-                            None
-                        }
-                    }
-                }
-                Some(download) = self.downloads.next() => {
-                    Some(self.end_download(download.expect("fail to spawn a task")))
-                }
-                else => {
-                    return Ok(());
-                }
-            };
+        "ConfigurationManager"
+    }
+
+    /// The default workflow applies to the main device,
+    /// while on the child devices the `downloaded` state is delegated to the child that installs the file.
+    fn workflows(&self) -> Vec<OperationWorkflow> {
+        let mut main_device = self.workflow();
+        main_device.filter.subsystem = Some(MAIN_DEVICE.to_string());
+
+        let mut child_devices = self.workflow();
+        child_devices.states.insert(
+            "downloaded".to_string(),
+            OperationState {
+                owner: "child".to_string(),
+                timeout: Some(CHILD_INSTALL_TIMEOUT),
+                next: vec!["successful".to_string(), "failed".to_string()],
+                ..Default::default()
+            },
+        );
+
+        vec![main_device, child_devices]
+    }
 
-            if let Some(response) = maybe_response {
-                self.message_box.send(response).await?
+    fn start(&mut self) {
+        self.file_transfer = self
+            .config
+            .file_transfer
+            .clone()
+            .map(FileTransferService::start);
+    }
+
+    fn update(
+        &mut self,
+        operation: &OperationKey,
+        state: ConfigUpdateState,
+        tasks: &mut OperationPluginTasks<ConfigUpdateState>,
+    ) -> Option<ConfigUpdateState> {
+        match state {
+            ConfigUpdateState::Init { request } => Some(self.init(operation, request)),
+            ConfigUpdateState::Scheduled { request } => {
+                Some(self.start_download(operation, request, tasks))
             }
+            ConfigUpdateState::Downloading { .. } => {
+                // Nothing to do while this plugin awaits for the actual end of the download
+                None
+            }
+            ConfigUpdateState::Downloaded {
+                request,
+                path,
+                checksum,
+            } => self.start_install(operation, request, path, checksum, tasks),
+            ConfigUpdateState::Installing { request, path } => {
+                self.start_install_task(operation, request, path, tasks)
+            }
+            ConfigUpdateState::Successful { .. }
+            | ConfigUpdateState::Failed { .. }
+            | ConfigUpdateState::Cancelled { .. }
+            | ConfigUpdateState::RolledBack { .. } => None,
         }
     }
-}
 
-impl ConfigManager {
-    pub fn name() -> &'static str {
-        "ConfigurationManager"
+    /// Remove the downloaded file, once the operation completed
+    fn release(&mut self, operation: &OperationKey, _state: ConfigUpdateState) {
+        self.remove_downloaded_file(operation);
+    }
+
+    /// Resume an operation found on start in a state owned by this plugin.
+    ///
+    /// All the steps are simply processed again, except the download
+    /// that has to be restarted when not known to be in progress.
+    fn recover(
+        &mut self,
+        operation: &OperationKey,
+        state: ConfigUpdateState,
+        tasks: &mut OperationPluginTasks<ConfigUpdateState>,
+    ) -> Option<ConfigUpdateState> {
+        match state {
+            ConfigUpdateState::Downloading { request, .. } if !tasks.is_pending(operation) => {
+                log::info!(
+                    "Restart the interrupted download for {}",
+                    String::from(operation)
+                );
+                self.start_download(operation, request, tasks);
+                None
+            }
+            state => self.update(operation, state, tasks),
+        }
+    }
+
+    /// Abort the download in progress, if any, for a cancelled operation.
+    ///
+    /// The `cancelled` state itself is published by the operations actor.
+    fn cancel(
+        &mut self,
+        operation: &OperationKey,
+        state: ConfigUpdateState,
+        tasks: &mut OperationPluginTasks<ConfigUpdateState>,
+    ) -> Option<ConfigUpdateState> {
+        if tasks.abort(operation) {
+            log::info!("Abort the download for {}", String::from(operation));
+            if let Some(path) = state.path() {
+                let _ = std::fs::remove_file(path);
+            }
+        }
+        None
     }
+}
 
-    pub fn new(
-        message_box: SimpleMessageBox<ConfigUpdateRequestState, ConfigUpdateRequestState>,
-    ) -> Self {
+impl ConfigManager {
+    pub fn new(config: ConfigPluginConfig) -> Self {
         ConfigManager {
-            message_box,
-            downloads: FuturesUnordered::new(),
+            config,
+            file_transfer: None,
         }
     }
 
-    /// A new request is immediately scheduled.
+    /// A new request is immediately scheduled, unless its target is unknown.
     ///
-    /// Having an init state with an automatic transition to an other step is done in order to:
-    /// - let the users plug their own behavior to check, prepare or adapt the request,
-    /// - while keeping unchanged the sub-systems that create these requests (i.e. the mappers).
-    fn init(&mut self, id: String, request: ConfigUpdateRequest) -> ConfigUpdateRequestState {
-        ConfigUpdateRequestState::Scheduled { id, request }
+    /// The targets of a child device being unknown to the gateway,
+    /// a request for a child device is scheduled provided the file transfer service is running.
+    fn init(
+        &mut self,
+        operation: &OperationKey,
+        request: ConfigUpdateRequest,
+    ) -> ConfigUpdateState {
+        let check = match child_device(operation) {
+            Some(child) => self.get_file_transfer(&child).map(|_| ()),
+            None => self.get_target(&request).map(|_| ()),
+        };
+        match check {
+            Ok(()) => ConfigUpdateState::Scheduled { request },
+            Err(reason) => ConfigUpdateState::Failed { request, reason },
+        }
+    }
+
+    fn get_target(&self, request: &ConfigUpdateRequest) -> Result<&ConfigTarget, String> {
+        self.config
+            .get_target(&request.target)
+            .ok_or_else(|| format!("Unknown configuration type: {}", request.target))
+    }
+
+    fn get_file_transfer(&self, child: &str) -> Result<&FileTransferService, String> {
+        let file_transfer = self.file_transfer.as_ref().ok_or_else(|| {
+            format!("Can not update the configuration of {child}: no file transfer service is configured")
+        })?;
+        file_transfer
+            .check()
+            .map_err(|err| format!("Can not update the configuration of {child}: {err}"))?;
+        Ok(file_transfer)
+    }
+
+    /// Remove the file downloaded for an operation, if any,
+    /// be it a temporary file or the file served to a child device
+    fn remove_downloaded_file(&self, operation: &OperationKey) {
+        let path = match (child_device(operation), &self.file_transfer) {
+            (Some(_), Some(file_transfer)) => file_transfer.path(&served_file_name(operation)),
+            (Some(_), None) => return,
+            (None, _) => download_path(operation),
+        };
+        let _ = std::fs::remove_file(format!("{path}.tmp"));
+        let _ = std::fs::remove_file(path);
     }
 
+    /// Download the new configuration in the background.
+    ///
+    /// For a child device, the url of the downloaded file is rewritten
+    /// so the child can get it from the file transfer service.
     fn start_download(
         &mut self,
-        id: String,
+        operation: &OperationKey,
         request: ConfigUpdateRequest,
-    ) -> ConfigUpdateRequestState {
-        let path = format!("/tmp/configuration.download.{id}");
-        let download = Download::new(&id, &request, &path);
-        self.downloads.push(tokio::spawn(download.execute()));
-        ConfigUpdateRequestState::Downloading { id, request, path }
-    }
-
-    fn end_download(&mut self, download: Download) -> ConfigUpdateRequestState {
-        ConfigUpdateRequestState::Downloaded {
-            id: download.id,
-            request: download.request,
-            path: download.path,
-        }
+        tasks: &mut OperationPluginTasks<ConfigUpdateState>,
+    ) -> ConfigUpdateState {
+        let (path, served_url) = match child_device(operation) {
+            Some(child) => {
+                let name = served_file_name(operation);
+                let served = self.get_file_transfer(&child).and_then(|file_transfer| {
+                    let url = file_transfer.url(&name)?;
+                    Ok((file_transfer.path(&name), url))
+                });
+                match served {
+                    Ok((path, url)) => (path, Some(url)),
+                    Err(reason) => return ConfigUpdateState::Failed { request, reason },
+                }
+            }
+            None => (download_path(operation), None),
+        };
+
+        let download = Download::new(&request, &path);
+        tasks.spawn(operation, async move {
+            match download.execute().await {
+                ConfigUpdateState::Downloaded {
+                    mut request,
+                    path,
+                    checksum,
+                } => {
+                    if let Some(url) = served_url {
+                        request.src_url = url;
+                    }
+                    Some(ConfigUpdateState::Downloaded {
+                        request,
+                        path,
+                        checksum,
+                    })
+                }
+                outcome => Some(outcome),
+            }
+        });
+        ConfigUpdateState::Downloading { request, path }
     }
 
-    /// The installation is immediately scheduled.
+    /// The installation is scheduled once the integrity of the downloaded file checked.
     ///
     /// Having a state with an automatic transition to an other step is done in order to:
     /// - let the users plug their own behavior to check, prepare or adapt the installation,
     /// - while keeping unchanged the sub-system that leads to this state (i.e. the downloader).
     fn start_install(
         &mut self,
-        id: String,
+        operation: &OperationKey,
         request: ConfigUpdateRequest,
         path: String,
-    ) -> ConfigUpdateRequestState {
-        ConfigUpdateRequestState::Installing { id, request, path }
+        checksum: String,
+        tasks: &mut OperationPluginTasks<ConfigUpdateState>,
+    ) -> Option<ConfigUpdateState> {
+        if let Some(child) = child_device(operation) {
+            let reason = format!(
+                "The configuration of {child} has to be installed by the child: \
+                 the downloaded state has to be delegated to the child"
+            );
+            return Some(ConfigUpdateState::Failed { request, reason });
+        }
+        tasks.spawn(operation, async move {
+            Some(match verify(&request, &path, checksum).await {
+                Ok(()) => ConfigUpdateState::Installing { request, path },
+                Err(reason) => ConfigUpdateState::Failed { request, reason },
+            })
+        });
+        None
     }
 
-    fn install(
+    /// Install the downloaded file in the background,
+    /// the outcome being sent when the installation completes.
+    fn start_install_task(
         &mut self,
-        id: String,
+        operation: &OperationKey,
         request: ConfigUpdateRequest,
-        _path: String,
-    ) -> ConfigUpdateRequestState {
-        ConfigUpdateRequestState::Successful { id, request }
+        path: String,
+        tasks: &mut OperationPluginTasks<ConfigUpdateState>,
+    ) -> Option<ConfigUpdateState> {
+        let target = match self.get_target(&request) {
+            Ok(target) => target.clone(),
+            Err(reason) => return Some(ConfigUpdateState::Failed { request, reason }),
+        };
+        tasks.spawn(operation, async move {
+            Some(match install(&target, &path).await {
+                Ok(()) => ConfigUpdateState::Successful { request },
+                Err(InstallError::Failed(reason)) => ConfigUpdateState::Failed { request, reason },
+                Err(InstallError::RolledBack(reason)) => {
+                    ConfigUpdateState::RolledBack { request, reason }
+                }
+            })
+        });
+        None
+    }
+}
+
+/// The path where is downloaded the new configuration of an operation
+fn download_path(operation: &OperationKey) -> String {
+    let file_name = format!(
+        "configuration.download.{}",
+        String::from(operation).replace('/', ".")
+    );
+    std::env::temp_dir()
+        .join(file_name)
+        .to_string_lossy()
+        .to_string()
+}
+
+/// The name of the file served to a child device for an operation
+fn served_file_name(operation: &OperationKey) -> String {
+    format!(
+        "configuration.{}",
+        String::from(operation).replace('/', ".")
+    )
+}
+
+/// The child device targeted by an operation, if not the main device
+fn child_device(operation: &OperationKey) -> Option<String> {
+    Some(operation.subsystem.clone()).filter(|subsystem| subsystem != MAIN_DEVICE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::configuration::file_transfer::FileTransferConfig;
+    use crate::operations_sm::plugin::unhandled_states;
+
+    fn child_operation() -> OperationKey {
+        OperationKey::try_from(&"tedge/operations/child-1/configuration/update/123".to_string())
+            .unwrap()
+    }
+
+    fn request() -> ConfigUpdateRequest {
+        ConfigUpdateRequest {
+            target: "mosquitto".to_string(),
+            src_url: "http://192.168.1.1:8000/mosquitto.conf".to_string(),
+            sha256: String::new(),
+        }
+    }
+
+    fn started(file_transfer: Option<FileTransferConfig>) -> ConfigManager {
+        let mut manager = ConfigManager::new(ConfigPluginConfig {
+            files: vec![],
+            file_transfer,
+        });
+        manager.start();
+        manager
+    }
+
+    fn failure_reason(state: ConfigUpdateState) -> String {
+        match state {
+            ConfigUpdateState::Failed { reason, .. } => reason,
+            state => panic!("Unexpected state: {state:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn schedule_the_child_updates_when_the_file_transfer_service_is_running() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut manager = started(Some(FileTransferConfig {
+            bind: "127.0.0.1:0".to_string(),
+            url: Some("http://192.168.1.10:8180".to_string()),
+            dir: dir.path().to_string_lossy().to_string(),
+        }));
+
+        let state = manager.init(&child_operation(), request());
+        assert!(matches!(state, ConfigUpdateState::Scheduled { .. }));
+    }
+
+    #[tokio::test]
+    async fn fail_the_child_updates_when_the_file_transfer_service_is_not_running() {
+        let mut manager = started(None);
+        let reason = failure_reason(manager.init(&child_operation(), request()));
+        assert!(reason.contains("no file transfer service"), "{reason}");
+
+        let dir = tempfile::tempdir().unwrap();
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut manager = started(Some(FileTransferConfig {
+            bind: listener.local_addr().unwrap().to_string(),
+            url: Some("http://192.168.1.10:8180".to_string()),
+            dir: dir.path().to_string_lossy().to_string(),
+        }));
+        let reason = failure_reason(manager.init(&child_operation(), request()));
+        assert!(reason.contains("Fail to bind"), "{reason}");
+
+        let mut manager = started(Some(FileTransferConfig {
+            bind: "0.0.0.0:0".to_string(),
+            url: None,
+            dir: dir.path().to_string_lossy().to_string(),
+        }));
+        let reason = failure_reason(manager.init(&child_operation(), request()));
+        assert!(reason.contains("No url"), "{reason}");
+    }
+
+    #[test]
+    fn delegate_by_default_the_installation_to_the_child_devices() {
+        let workflows = ConfigManager::new(ConfigPluginConfig::default()).workflows();
+        let [main_device, child_devices] = workflows.as_slice() else {
+            panic!("Expected a workflow for the main device and one for the child devices");
+        };
+
+        assert_eq!(main_device.filter.subsystem.as_deref(), Some(MAIN_DEVICE));
+        assert_eq!(main_device.states["downloaded"].owner, "tedge");
+
+        assert_eq!(child_devices.filter.subsystem, None);
+        assert_eq!(child_devices.states["downloaded"].owner, "child");
+        assert_eq!(
+            child_devices.states["downloaded"].timeout,
+            Some(CHILD_INSTALL_TIMEOUT)
+        );
+        assert!(unhandled_states(child_devices, ConfigManager::HANDLED_STATES).is_empty());
     }
 }
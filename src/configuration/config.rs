@@ -0,0 +1,74 @@
+use crate::configuration::file_transfer::FileTransferConfig;
+use crate::operations_sm::plugin::PluginConfig;
+use serde::{Deserialize, Serialize};
+
+/// The configuration of the configuration plugin
+///
+/// Lists the configuration files managed by the plugin.
+///
+/// ```toml
+/// [[files]]
+/// type = "mosquitto"
+/// path = "/etc/mosquitto/mosquitto.conf"
+/// user = "mosquitto"
+/// group = "mosquitto"
+/// mode = 0o644
+/// post_install = "systemctl restart mosquitto"
+/// backups = 3
+///
+/// [file_transfer]
+/// url = "http://192.168.1.10:8180"
+/// ```
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ConfigPluginConfig {
+    #[serde(default)]
+    pub files: Vec<ConfigTarget>,
+
+    /// The service used to serve to the child devices the downloaded configuration files
+    ///
+    /// The configuration updates of the child devices fail when not set or not running.
+    pub file_transfer: Option<FileTransferConfig>,
+}
+
+/// A configuration file managed by the configuration plugin
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ConfigTarget {
+    /// The configuration type, as used by the requests to designate this file
+    #[serde(rename = "type")]
+    pub config_type: String,
+
+    /// Where the configuration file is installed
+    pub path: String,
+
+    /// The user owning the installed file
+    pub user: Option<String>,
+
+    /// The group owning the installed file
+    pub group: Option<String>,
+
+    /// The permissions of the installed file
+    pub mode: Option<u32>,
+
+    /// Possibly a command to be run once the file installed (e.g. to validate or reload the configuration)
+    ///
+    /// On failure, the previous version of the file is restored.
+    pub post_install: Option<String>,
+
+    /// The number of previous versions of the file kept as backups
+    #[serde(default = "default_backups")]
+    pub backups: usize,
+}
+
+fn default_backups() -> usize {
+    1
+}
+
+impl ConfigPluginConfig {
+    pub fn get_target(&self, config_type: &str) -> Option<&ConfigTarget> {
+        self.files
+            .iter()
+            .find(|target| target.config_type == config_type)
+    }
+}
+
+impl PluginConfig for ConfigPluginConfig {}
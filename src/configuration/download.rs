@@ -0,0 +1,192 @@
+use sha2::{Digest, Sha256};
+use std::path::Path;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+/// The maximum time to establish a connection with an HTTP server
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The maximum time to wait for the response headers, and then for each chunk of the response
+///
+/// For an upload, this is the maximum time to send the file and to receive the response headers.
+pub const READ_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// The HTTP client used to download and upload files
+///
+/// A server that doesn't accept the connection in time makes the request fail,
+/// instead of leaving the operation stuck in its `downloading` or `uploading` state.
+pub fn http_client() -> Result<reqwest::Client, String> {
+    reqwest::Client::builder()
+        .connect_timeout(CONNECT_TIMEOUT)
+        .build()
+        .map_err(|err| format!("Fail to create an HTTP client: {err}"))
+}
+
+/// Download the content of an `http://`, `https://` or `file://` url into a file.
+///
+/// The content is first streamed to a temporary file, which is renamed only on success,
+/// so the `path` is never left with a partial content.
+pub async fn download(src_url: &str, path: &str) -> Result<(), String> {
+    let tmp_path = format!("{path}.tmp");
+    let result = if let Some(src_path) = src_url.strip_prefix("file://") {
+        copy_file(src_path, &tmp_path).await
+    } else if src_url.starts_with("http://") || src_url.starts_with("https://") {
+        fetch_http(src_url, &tmp_path, READ_TIMEOUT).await
+    } else {
+        Err(format!("Unsupported url scheme: {src_url}"))
+    };
+
+    match result {
+        Ok(()) => tokio::fs::rename(&tmp_path, path)
+            .await
+            .map_err(|err| format!("Fail to move the downloaded file to {path}: {err}")),
+        Err(err) => {
+            let _ = tokio::fs::remove_file(&tmp_path).await;
+            Err(err)
+        }
+    }
+}
+
+async fn copy_file(src_path: &str, path: &str) -> Result<(), String> {
+    if !Path::new(src_path).is_file() {
+        return Err(format!("No such file: {src_path}"));
+    }
+    tokio::fs::copy(src_path, path)
+        .await
+        .map(|_| ())
+        .map_err(|err| format!("Fail to copy {src_path} to {path}: {err}"))
+}
+
+/// Fetch the content of an HTTP url,
+/// failing if the server doesn't respond or stops sending data for longer than `read_timeout`
+async fn fetch_http(src_url: &str, path: &str, read_timeout: Duration) -> Result<(), String> {
+    let timed_out = |_| format!("Fail to download {src_url}: timeout after {read_timeout:?}");
+    let request = http_client()?.get(src_url).send();
+    let mut response = tokio::time::timeout(read_timeout, request)
+        .await
+        .map_err(timed_out)?
+        .map_err(|err| format!("Fail to connect to {src_url}: {err}"))?;
+
+    let status = response.status();
+    if !status.is_success() {
+        return Err(format!("Fail to download {src_url}: HTTP {status}"));
+    }
+
+    let mut file = tokio::fs::File::create(path)
+        .await
+        .map_err(|err| format!("Fail to create {path}: {err}"))?;
+    while let Some(chunk) = tokio::time::timeout(read_timeout, response.chunk())
+        .await
+        .map_err(timed_out)?
+        .map_err(|err| format!("Fail to download {src_url}: {err}"))?
+    {
+        file.write_all(&chunk)
+            .await
+            .map_err(|err| format!("Fail to write {path}: {err}"))?;
+    }
+    file.flush()
+        .await
+        .map_err(|err| format!("Fail to write {path}: {err}"))
+}
+
+/// Compute the sha256 digest of a file, as an hexadecimal string
+pub async fn sha256_digest(path: &str) -> Result<String, String> {
+    let mut file = tokio::fs::File::open(path)
+        .await
+        .map_err(|err| format!("Fail to open {path}: {err}"))?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; 8192];
+    loop {
+        let n = file
+            .read(&mut buffer)
+            .await
+            .map_err(|err| format!("Fail to read {path}: {err}"))?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buffer[..n]);
+    }
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+
+    /// A local stand-in for an HTTP server,
+    /// answering the first request with a raw response and then keeping the connection open
+    fn http_stand_in(response: &'static str) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/config", listener.local_addr().unwrap());
+        std::thread::spawn(move || {
+            if let Ok((mut stream, _)) = listener.accept() {
+                let mut request = [0; 1024];
+                let _ = stream.read(&mut request);
+                let _ = stream.write_all(response.as_bytes());
+                std::thread::sleep(Duration::from_secs(5));
+            }
+        });
+        url
+    }
+
+    fn target_path(dir: &tempfile::TempDir) -> String {
+        dir.path().join("config").to_string_lossy().to_string()
+    }
+
+    #[tokio::test]
+    async fn download_a_file_over_http() {
+        let url = http_stand_in("HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello");
+        let dir = tempfile::tempdir().unwrap();
+        let path = target_path(&dir);
+
+        download(&url, &path).await.unwrap();
+
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "hello");
+        assert!(!Path::new(&format!("{path}.tmp")).exists());
+    }
+
+    #[tokio::test]
+    async fn report_http_errors() {
+        let url = http_stand_in("HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n");
+        let dir = tempfile::tempdir().unwrap();
+        let path = target_path(&dir);
+
+        let err = download(&url, &path).await.unwrap_err();
+
+        assert!(err.contains("HTTP 404"), "{err}");
+        assert!(!Path::new(&path).exists());
+        assert!(!Path::new(&format!("{path}.tmp")).exists());
+    }
+
+    #[tokio::test]
+    async fn give_up_on_a_server_that_never_responds() {
+        let url = http_stand_in("");
+        let dir = tempfile::tempdir().unwrap();
+        let path = target_path(&dir);
+
+        let err = fetch_http(&url, &path, Duration::from_millis(200))
+            .await
+            .unwrap_err();
+
+        assert!(err.contains("timeout"), "{err}");
+    }
+
+    #[tokio::test]
+    async fn give_up_on_a_server_that_stops_sending_data() {
+        let url = http_stand_in("HTTP/1.1 200 OK\r\nContent-Length: 1000\r\n\r\npartial");
+        let dir = tempfile::tempdir().unwrap();
+        let path = target_path(&dir);
+
+        let err = fetch_http(&url, &path, Duration::from_millis(200))
+            .await
+            .unwrap_err();
+
+        assert!(err.contains("timeout"), "{err}");
+    }
+}
@@ -0,0 +1,305 @@
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use serde::{Deserialize, Serialize};
+use std::convert::Infallible;
+use std::future::Future;
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+/// The configuration of the file transfer service,
+/// used to serve to the child devices the files downloaded by the gateway.
+///
+/// ```toml
+/// [file_transfer]
+/// bind = "0.0.0.0:8180"
+/// url = "http://192.168.1.10:8180"
+/// dir = "/var/tedge/file-transfer"
+/// ```
+///
+/// The service has no authentication: any host that can reach the `bind` address can get the served files.
+/// The `bind` address should therefore be restricted to the network interface of the child devices.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct FileTransferConfig {
+    /// The address on which the service listens
+    #[serde(default = "default_bind")]
+    pub bind: String,
+
+    /// The base url of the service, as reachable by the child devices
+    ///
+    /// Defaults to `http://{bind}`, when the service listens on a specific address of the gateway.
+    pub url: Option<String>,
+
+    /// The directory of the served files
+    #[serde(default = "default_dir")]
+    pub dir: String,
+}
+
+fn default_bind() -> String {
+    "0.0.0.0:8180".to_string()
+}
+
+fn default_dir() -> String {
+    std::env::temp_dir()
+        .join("file-transfer")
+        .to_string_lossy()
+        .to_string()
+}
+
+impl FileTransferConfig {
+    /// The local path of a served file
+    pub fn path(&self, name: &str) -> String {
+        PathBuf::from(&self.dir)
+            .join(name)
+            .to_string_lossy()
+            .to_string()
+    }
+
+    /// The base url of the service, as reachable by the child devices
+    ///
+    /// A loopback url is rejected, as not reachable by the child devices,
+    /// and so is a missing url when the service listens on all the interfaces.
+    pub fn base_url(&self) -> Result<String, String> {
+        let url = match &self.url {
+            Some(url) => url.trim_end_matches('/').to_string(),
+            None => {
+                let addr = self.bind_addr()?;
+                if addr.ip().is_unspecified() {
+                    return Err(format!(
+                        "No url is given for the file transfer service listening on {addr}"
+                    ));
+                }
+                format!("http://{addr}")
+            }
+        };
+        if is_loopback(&url) {
+            return Err(format!(
+                "The file transfer url {url} is not reachable by the child devices"
+            ));
+        }
+        Ok(url)
+    }
+
+    fn bind_addr(&self) -> Result<SocketAddr, String> {
+        self.bind
+            .parse()
+            .map_err(|err| format!("Invalid file transfer address {}: {err}", self.bind))
+    }
+}
+
+/// Tell if the host of an url is a loopback address
+fn is_loopback(url: &str) -> bool {
+    let authority = url.split_once("://").map_or(url, |(_, rest)| rest);
+    let authority = authority.split('/').next().unwrap_or_default();
+    let host = match authority.strip_prefix('[') {
+        Some(ipv6) => ipv6.split(']').next().unwrap_or_default(),
+        None => authority.split(':').next().unwrap_or_default(),
+    };
+    host.eq_ignore_ascii_case("localhost")
+        || host.parse::<IpAddr>().is_ok_and(|ip| ip.is_loopback())
+}
+
+/// The file transfer service, as started by the configuration plugin
+pub struct FileTransferService {
+    config: FileTransferConfig,
+
+    /// The base url of the served files while the service is running,
+    /// or the reason why the service is not running
+    status: Arc<Mutex<Result<String, String>>>,
+}
+
+impl FileTransferService {
+    /// Bind the service to its address, then serve the files in the background.
+    ///
+    /// A failure only affects the configuration updates of the child devices:
+    /// it is logged and kept as the status of the service.
+    pub fn start(config: FileTransferConfig) -> Self {
+        let server = config
+            .base_url()
+            .and_then(|url| bind(&config).map(|server| (url, server)));
+        let status = match server {
+            Ok((url, server)) => {
+                let status = Arc::new(Mutex::new(Ok(url)));
+                let server_status = status.clone();
+                tokio::spawn(async move {
+                    if let Err(err) = server.await {
+                        let err = format!("File transfer service failed: {err}");
+                        log::error!("{err}");
+                        if let Ok(mut status) = server_status.lock() {
+                            *status = Err(err);
+                        }
+                    }
+                });
+                status
+            }
+            Err(err) => {
+                log::error!("{err}");
+                Arc::new(Mutex::new(Err(err)))
+            }
+        };
+        FileTransferService { config, status }
+    }
+
+    /// Check that the service is running
+    pub fn check(&self) -> Result<(), String> {
+        self.base_url().map(|_| ())
+    }
+
+    /// The local path of a served file
+    pub fn path(&self, name: &str) -> String {
+        self.config.path(name)
+    }
+
+    /// The url of a served file, as reachable by the child devices
+    pub fn url(&self, name: &str) -> Result<String, String> {
+        self.base_url()
+            .map(|url| format!("{url}/file-transfer/{name}"))
+    }
+
+    fn base_url(&self) -> Result<String, String> {
+        match self.status.lock() {
+            Ok(status) => status.clone(),
+            Err(_) => Err("The file transfer service failed".to_string()),
+        }
+    }
+}
+
+/// Bind the HTTP server serving the files of the file transfer directory,
+/// on `GET /file-transfer/{name}` requests.
+fn bind(
+    config: &FileTransferConfig,
+) -> Result<impl Future<Output = Result<(), hyper::Error>>, String> {
+    let addr = config.bind_addr()?;
+    std::fs::create_dir_all(&config.dir)
+        .map_err(|err| format!("Fail to create {}: {err}", config.dir))?;
+
+    let dir = config.dir.clone();
+    let make_service = make_service_fn(move |_| {
+        let dir = dir.clone();
+        async move { Ok::<_, Infallible>(service_fn(move |request| get_file(dir.clone(), request))) }
+    });
+    let server = Server::try_bind(&addr)
+        .map_err(|err| format!("Fail to bind the file transfer service to {addr}: {err}"))?
+        .serve(make_service);
+    Ok(server)
+}
+
+async fn get_file(dir: String, request: Request<Body>) -> Result<Response<Body>, Infallible> {
+    let name = request.uri().path().strip_prefix("/file-transfer/");
+    let response = match (request.method(), name) {
+        (&Method::GET, Some(name)) if is_valid_name(name) => {
+            match tokio::fs::read(PathBuf::from(dir).join(name)).await {
+                Ok(content) => Response::new(Body::from(content)),
+                Err(_) => status(StatusCode::NOT_FOUND),
+            }
+        }
+        (&Method::GET, _) => status(StatusCode::NOT_FOUND),
+        _ => status(StatusCode::METHOD_NOT_ALLOWED),
+    };
+    Ok(response)
+}
+
+/// Only the files of the file transfer directory can be served
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty() && !name.contains('/') && !name.contains("..")
+}
+
+fn status(code: StatusCode) -> Response<Body> {
+    let mut response = Response::new(Body::empty());
+    *response.status_mut() = code;
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    fn file_transfer(bind: &str, url: Option<&str>) -> FileTransferConfig {
+        FileTransferConfig {
+            bind: bind.to_string(),
+            url: url.map(str::to_string),
+            dir: default_dir(),
+        }
+    }
+
+    #[test]
+    fn the_url_defaults_to_the_bind_address() {
+        let config = file_transfer("192.168.1.10:8180", None);
+        assert_eq!(config.base_url().unwrap(), "http://192.168.1.10:8180");
+
+        let config = file_transfer("0.0.0.0:8180", Some("http://192.168.1.10:8180/"));
+        assert_eq!(config.base_url().unwrap(), "http://192.168.1.10:8180");
+    }
+
+    #[test]
+    fn the_url_has_to_be_reachable_by_the_child_devices() {
+        assert!(file_transfer("0.0.0.0:8180", None).base_url().is_err());
+        assert!(file_transfer("127.0.0.1:8180", None).base_url().is_err());
+        for url in [
+            "http://localhost:8180",
+            "http://127.0.0.1:8180",
+            "http://[::1]:8180/",
+        ] {
+            assert!(file_transfer("0.0.0.0:8180", Some(url)).base_url().is_err());
+        }
+    }
+
+    #[tokio::test]
+    async fn serve_the_files_of_the_file_transfer_directory() {
+        let dir = tempfile::tempdir().unwrap();
+        let port = free_port();
+        let config = FileTransferConfig {
+            bind: format!("127.0.0.1:{port}"),
+            url: Some("http://192.168.1.10:8180".to_string()),
+            dir: dir.path().to_string_lossy().to_string(),
+        };
+        std::fs::write(config.path("mosquitto.conf"), "listener 1883").unwrap();
+        let service = FileTransferService::start(config);
+
+        assert!(service.check().is_ok());
+        assert_eq!(
+            service.url("mosquitto.conf").unwrap(),
+            "http://192.168.1.10:8180/file-transfer/mosquitto.conf"
+        );
+        let response = get(port, "/file-transfer/mosquitto.conf").await;
+        assert!(response.starts_with("HTTP/1.1 200"));
+        assert!(response.ends_with("listener 1883"));
+        let response = get(port, "/file-transfer/..%2Fsecret").await;
+        assert!(response.starts_with("HTTP/1.1 404"));
+    }
+
+    #[tokio::test]
+    async fn keep_the_bind_failure_as_status() {
+        let dir = tempfile::tempdir().unwrap();
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let config = FileTransferConfig {
+            bind: format!("127.0.0.1:{port}"),
+            url: Some("http://192.168.1.10:8180".to_string()),
+            dir: dir.path().to_string_lossy().to_string(),
+        };
+        let service = FileTransferService::start(config);
+
+        let err = service.check().unwrap_err();
+        assert!(err.contains("Fail to bind"), "{err}");
+        assert!(service.url("mosquitto.conf").is_err());
+    }
+
+    fn free_port() -> u16 {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.local_addr().unwrap().port()
+    }
+
+    async fn get(port: u16, path: &str) -> String {
+        let mut stream = tokio::net::TcpStream::connect(("127.0.0.1", port))
+            .await
+            .unwrap();
+        let request =
+            format!("GET {path} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n");
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        response
+    }
+}
@@ -0,0 +1,321 @@
+use crate::configuration::config::ConfigTarget;
+use crate::operations_sm::script::run_command;
+use nix::unistd::{Group, User};
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+
+/// Why an installation failed
+#[derive(Debug)]
+pub enum InstallError {
+    /// The new file has not been installed, the previous one being left unchanged
+    Failed(String),
+
+    /// The new file has been installed, but the post-install command failed
+    /// and the previous file has been restored
+    RolledBack(String),
+}
+
+/// Install a downloaded configuration file as declared by its target
+///
+/// - the current file, if any, is copied aside,
+/// - the new file is copied next to the target path with the declared owner and permissions,
+///   then atomically renamed into place,
+/// - the post-install command, if any, is run to validate or reload the new configuration,
+///   the previous file being restored on failure,
+/// - only then, the previous file is added to the backups, the oldest ones being removed.
+///
+/// The existing backups are left untouched when the installation fails.
+pub async fn install(target: &ConfigTarget, downloaded_path: &str) -> Result<(), InstallError> {
+    let path = &target.path;
+    let previous_path = save_current(target).await.map_err(InstallError::Failed)?;
+
+    if let Err(err) = replace(target, downloaded_path).await {
+        discard_current(previous_path.as_deref()).await;
+        return Err(InstallError::Failed(err));
+    }
+
+    if let Some(command) = &target.post_install {
+        if let Err(err) = run_command(command).await {
+            let reason = match rollback(target, previous_path.as_deref()).await {
+                Ok(()) => format!("{err}. The previous version of {path} has been restored"),
+                Err(rollback_err) => format!("{err}. {rollback_err}"),
+            };
+            return Err(InstallError::RolledBack(reason));
+        }
+    }
+
+    if let Some(previous_path) = previous_path {
+        rotate_backups(target, &previous_path).await;
+    }
+    Ok(())
+}
+
+/// Atomically replace the target file by a new one, with the declared owner and permissions
+async fn replace(target: &ConfigTarget, new_path: &str) -> Result<(), String> {
+    let path = &target.path;
+    let tmp_path = format!("{path}.tmp");
+    tokio::fs::copy(new_path, &tmp_path)
+        .await
+        .map_err(|err| format!("Fail to copy {new_path} to {tmp_path}: {err}"))?;
+
+    let result = async {
+        set_permissions(target, &tmp_path).await?;
+        tokio::fs::rename(&tmp_path, path)
+            .await
+            .map_err(|err| format!("Fail to move {tmp_path} to {path}: {err}"))
+    }
+    .await;
+    if result.is_err() {
+        let _ = tokio::fs::remove_file(&tmp_path).await;
+    }
+    result
+}
+
+/// Copy aside the current version of the target file, if any, to rollback on failure.
+///
+/// Return the path of the copy.
+async fn save_current(target: &ConfigTarget) -> Result<Option<String>, String> {
+    let path = &target.path;
+    if !Path::new(path).exists() {
+        return Ok(None);
+    }
+
+    let saved_path = format!("{path}.bak.new");
+    tokio::fs::copy(path, &saved_path)
+        .await
+        .map_err(|err| format!("Fail to backup {path} to {saved_path}: {err}"))?;
+    Ok(Some(saved_path))
+}
+
+/// Remove the copy of the current file, when not used as a backup
+async fn discard_current(saved_path: Option<&str>) {
+    if let Some(saved_path) = saved_path {
+        let _ = tokio::fs::remove_file(saved_path).await;
+    }
+}
+
+/// Make the copy of the replaced file the most recent backup,
+/// shifting the previous backups so only the most recent ones are kept.
+async fn rotate_backups(target: &ConfigTarget, saved_path: &str) {
+    let path = &target.path;
+    let count = target.backups;
+    if count == 0 {
+        discard_current(Some(saved_path)).await;
+    } else {
+        for index in (1..count).rev() {
+            let previous = backup_path(path, index);
+            if Path::new(&previous).exists() {
+                let _ = tokio::fs::rename(&previous, backup_path(path, index + 1)).await;
+            }
+        }
+        let _ = tokio::fs::rename(saved_path, backup_path(path, 1)).await;
+    }
+    prune_backups(target).await;
+}
+
+/// Restore the copy of the previous version of the target file,
+/// or remove the target file if there was no previous version.
+///
+/// The copy is kept if it can't be restored.
+async fn rollback(target: &ConfigTarget, backup_path: Option<&str>) -> Result<(), String> {
+    let path = &target.path;
+    match backup_path {
+        Some(backup_path) => {
+            replace(target, backup_path)
+                .await
+                .map_err(|err| format!("Fail to restore the backup {backup_path}: {err}"))?;
+            discard_current(Some(backup_path)).await;
+        }
+        None => tokio::fs::remove_file(path)
+            .await
+            .map_err(|err| format!("Fail to remove {path}: {err}"))?,
+    }
+    if let Some(command) = &target.post_install {
+        run_command(command).await.map_err(|err| {
+            format!("The previous version of {path} has been restored, but {err}")
+        })?;
+    }
+    Ok(())
+}
+
+/// Remove the backups in excess
+async fn prune_backups(target: &ConfigTarget) {
+    let mut index = target.backups + 1;
+    loop {
+        let backup_path = backup_path(&target.path, index);
+        if tokio::fs::remove_file(&backup_path).await.is_err() {
+            break;
+        }
+        index += 1;
+    }
+}
+
+fn backup_path(path: &str, index: usize) -> String {
+    format!("{path}.bak.{index}")
+}
+
+/// Set the owner and the permissions of a file as declared by its target
+pub async fn set_permissions(target: &ConfigTarget, path: &str) -> Result<(), String> {
+    let uid = target.user.as_deref().map(user_id).transpose()?;
+    let gid = target.group.as_deref().map(group_id).transpose()?;
+    if uid.is_some() || gid.is_some() {
+        std::os::unix::fs::chown(path, uid, gid)
+            .map_err(|err| format!("Fail to set the owner of {path}: {err}"))?;
+    }
+
+    if let Some(mode) = target.mode {
+        tokio::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))
+            .await
+            .map_err(|err| format!("Fail to set the permissions of {path}: {err}"))?;
+    }
+    Ok(())
+}
+
+/// The id of a user given by name or by id
+fn user_id(user: &str) -> Result<u32, String> {
+    if let Ok(uid) = user.parse() {
+        return Ok(uid);
+    }
+    match User::from_name(user) {
+        Ok(Some(user)) => Ok(user.uid.as_raw()),
+        Ok(None) => Err(format!("Unknown user: {user}")),
+        Err(err) => Err(format!("Fail to get the id of the user {user}: {err}")),
+    }
+}
+
+/// The id of a group given by name or by id
+fn group_id(group: &str) -> Result<u32, String> {
+    if let Ok(gid) = group.parse() {
+        return Ok(gid);
+    }
+    match Group::from_name(group) {
+        Ok(Some(group)) => Ok(group.gid.as_raw()),
+        Ok(None) => Err(format!("Unknown group: {group}")),
+        Err(err) => Err(format!("Fail to get the id of the group {group}: {err}")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::MetadataExt;
+
+    fn target(path: &str, user: &str, mode: Option<u32>) -> ConfigTarget {
+        ConfigTarget {
+            config_type: "test".to_string(),
+            path: path.to_string(),
+            user: Some(user.to_string()),
+            group: None,
+            mode,
+            post_install: None,
+            backups: 1,
+        }
+    }
+
+    /// A configuration file with two backups, and a new version to be installed
+    fn installed_target(
+        dir: &tempfile::TempDir,
+        post_install: &str,
+    ) -> (ConfigTarget, String, String) {
+        let path = dir.path().join("config").to_string_lossy().to_string();
+        let new_path = dir.path().join("config.new").to_string_lossy().to_string();
+        std::fs::write(&path, "current").unwrap();
+        std::fs::write(backup_path(&path, 1), "backup 1").unwrap();
+        std::fs::write(backup_path(&path, 2), "backup 2").unwrap();
+        std::fs::write(&new_path, "new").unwrap();
+        let target = ConfigTarget {
+            config_type: "test".to_string(),
+            path: path.clone(),
+            user: None,
+            group: None,
+            mode: None,
+            post_install: Some(post_install.to_string()),
+            backups: 2,
+        };
+        (target, path, new_path)
+    }
+
+    fn read(path: &str) -> String {
+        std::fs::read_to_string(path).unwrap_or_default()
+    }
+
+    #[tokio::test]
+    async fn rotate_the_backups_once_installed() {
+        let dir = tempfile::tempdir().unwrap();
+        let (target, path, new_path) = installed_target(&dir, "true");
+
+        install(&target, &new_path).await.unwrap();
+
+        assert_eq!(read(&path), "new");
+        assert_eq!(read(&backup_path(&path, 1)), "current");
+        assert_eq!(read(&backup_path(&path, 2)), "backup 1");
+        assert!(!Path::new(&backup_path(&path, 3)).exists());
+        assert!(!Path::new(&format!("{path}.bak.new")).exists());
+    }
+
+    #[tokio::test]
+    async fn keep_the_backups_unchanged_on_rollback() {
+        let dir = tempfile::tempdir().unwrap();
+        let (target, path, new_path) = installed_target(&dir, "false");
+
+        let result = install(&target, &new_path).await;
+
+        assert!(matches!(result, Err(InstallError::RolledBack(_))));
+        assert_eq!(read(&path), "current");
+        assert_eq!(read(&backup_path(&path, 1)), "backup 1");
+        assert_eq!(read(&backup_path(&path, 2)), "backup 2");
+        assert!(!Path::new(&format!("{path}.bak.new")).exists());
+    }
+
+    #[tokio::test]
+    async fn keep_the_backups_unchanged_on_failure() {
+        let dir = tempfile::tempdir().unwrap();
+        let (target, path, _) = installed_target(&dir, "true");
+        let missing_path = dir.path().join("missing").to_string_lossy().to_string();
+
+        let result = install(&target, &missing_path).await;
+
+        assert!(matches!(result, Err(InstallError::Failed(_))));
+        assert_eq!(read(&path), "current");
+        assert_eq!(read(&backup_path(&path, 1)), "backup 1");
+        assert_eq!(read(&backup_path(&path, 2)), "backup 2");
+        assert!(!Path::new(&format!("{path}.bak.new")).exists());
+    }
+
+    #[test]
+    fn resolve_users_and_groups_by_name_or_id() {
+        assert_eq!(user_id("root"), Ok(0));
+        assert_eq!(user_id("0"), Ok(0));
+        assert_eq!(group_id("root"), Ok(0));
+        assert_eq!(group_id("0"), Ok(0));
+        assert!(user_id("no such user").is_err());
+        assert!(group_id("no such group").is_err());
+    }
+
+    #[tokio::test]
+    async fn set_the_declared_owner_and_mode() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config").to_string_lossy().to_string();
+        std::fs::write(&path, "content").unwrap();
+        let uid = std::fs::metadata(&path).unwrap().uid();
+        let target = target(&path, &uid.to_string(), Some(0o600));
+
+        set_permissions(&target, &path).await.unwrap();
+
+        let metadata = std::fs::metadata(&path).unwrap();
+        assert_eq!(metadata.uid(), uid);
+        assert_eq!(metadata.mode() & 0o777, 0o600);
+    }
+
+    #[tokio::test]
+    async fn reject_an_unknown_owner() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config").to_string_lossy().to_string();
+        std::fs::write(&path, "content").unwrap();
+        let target = target(&path, "no such user", None);
+
+        let err = set_permissions(&target, &path).await.unwrap_err();
+
+        assert!(err.contains("Unknown user"), "{err}");
+    }
+}
@@ -0,0 +1,171 @@
+use crate::configuration::config::ConfigPluginConfig;
+use crate::configuration::download::sha256_digest;
+use crate::configuration::list::messages::{ConfigFileInfo, ConfigListState};
+use crate::operations_sm::config::OperationKey;
+use crate::operations_sm::plugin::{OperationPlugin, OperationPluginTasks};
+use std::path::Path;
+
+/// Plugin that handles the configuration list requests.
+pub struct ConfigListManager {
+    /// The configuration files managed by the configuration plugin
+    config: ConfigPluginConfig,
+}
+
+impl OperationPlugin for ConfigListManager {
+    type State = ConfigListState;
+
+    const HANDLED_STATES: &'static [&'static str] = &["init"];
+
+    fn name(&self) -> &str {
+        "ConfigurationListManager"
+    }
+
+    fn update(
+        &mut self,
+        operation: &OperationKey,
+        state: ConfigListState,
+        tasks: &mut OperationPluginTasks<ConfigListState>,
+    ) -> Option<ConfigListState> {
+        match state {
+            ConfigListState::Init {} => {
+                self.start_listing(operation, tasks);
+                None
+            }
+            ConfigListState::Successful { .. }
+            | ConfigListState::Failed { .. }
+            | ConfigListState::Cancelled { .. } => None,
+        }
+    }
+}
+
+impl ConfigListManager {
+    pub fn new(config: ConfigPluginConfig) -> Self {
+        ConfigListManager { config }
+    }
+
+    /// List the registered configuration files in the background,
+    /// computing the checksum of the current version of each file.
+    fn start_listing(
+        &mut self,
+        operation: &OperationKey,
+        tasks: &mut OperationPluginTasks<ConfigListState>,
+    ) {
+        let targets = self.config.files.clone();
+        tasks.spawn(operation, async move {
+            let mut files = Vec::with_capacity(targets.len());
+            for target in targets {
+                let checksum = if Path::new(&target.path).exists() {
+                    match sha256_digest(&target.path).await {
+                        Ok(checksum) => Some(checksum),
+                        Err(reason) => return Some(ConfigListState::Failed { reason }),
+                    }
+                } else {
+                    None
+                };
+                files.push(ConfigFileInfo {
+                    config_type: target.config_type,
+                    path: target.path,
+                    checksum,
+                });
+            }
+            Some(ConfigListState::Successful { files })
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::configuration::config::ConfigTarget;
+    use serde_json::json;
+    use tempfile::TempDir;
+
+    fn target(config_type: &str, path: &Path) -> ConfigTarget {
+        ConfigTarget {
+            config_type: config_type.to_string(),
+            path: path.to_string_lossy().to_string(),
+            user: None,
+            group: None,
+            mode: None,
+            post_install: None,
+            backups: 1,
+        }
+    }
+
+    async fn list_files(files: Vec<ConfigTarget>) -> ConfigListState {
+        let mut manager = ConfigListManager::new(ConfigPluginConfig {
+            files,
+            file_transfer: None,
+        });
+        let mut tasks = OperationPluginTasks::new();
+        let operation = OperationKey::try_from(
+            &"tedge/operations/main-device/configuration/list/1".to_string(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            manager.update(&operation, ConfigListState::Init {}, &mut tasks),
+            None
+        );
+        let (_, _, outcome) = tasks.next().await.unwrap();
+        outcome.unwrap().unwrap()
+    }
+
+    fn path(dir: &TempDir, name: &str) -> std::path::PathBuf {
+        dir.path().join(name)
+    }
+
+    #[tokio::test]
+    async fn list_the_configuration_files_with_their_checksum() {
+        let dir = tempfile::tempdir().unwrap();
+        let mosquitto = path(&dir, "mosquitto.conf");
+        std::fs::write(&mosquitto, "listener 1883").unwrap();
+        let missing = path(&dir, "tedge.toml");
+
+        let outcome = list_files(vec![
+            target("mosquitto", &mosquitto),
+            target("tedge", &missing),
+        ])
+        .await;
+
+        assert_eq!(
+            serde_json::to_value(outcome).unwrap(),
+            json!({
+                "status": "successful",
+                "files": [
+                    {
+                        "type": "mosquitto",
+                        "path": mosquitto,
+                        "checksum": "579edb5716d870ae6b12a69bd1b8863b1c74117c4c1b74b28b661f4a5da9fed0",
+                    },
+                    {
+                        "type": "tedge",
+                        "path": missing,
+                    }
+                ]
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn list_no_files_when_none_is_registered() {
+        assert_eq!(
+            list_files(vec![]).await,
+            ConfigListState::Successful { files: vec![] }
+        );
+    }
+
+    #[tokio::test]
+    async fn fail_the_listing_when_a_file_can_not_be_read() {
+        let dir = tempfile::tempdir().unwrap();
+        let not_a_file = path(&dir, "conf.d");
+        std::fs::create_dir(&not_a_file).unwrap();
+
+        let outcome = list_files(vec![target("conf.d", &not_a_file)]).await;
+
+        let ConfigListState::Failed { reason } = outcome else {
+            panic!("Unexpected state: {outcome:?}");
+        };
+        assert!(reason.contains("conf.d"), "{reason}");
+    }
+}
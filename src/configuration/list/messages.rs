@@ -0,0 +1,44 @@
+use serde::Deserialize;
+use serde::Serialize;
+use tedge_mqtt_state_machine_macros::OperationState;
+
+/// A configuration file as reported by a configuration list operation
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct ConfigFileInfo {
+    /// The configuration type, as used by the requests to designate this file
+    #[serde(rename = "type")]
+    pub config_type: String,
+
+    /// Where the configuration file is installed
+    pub path: String,
+
+    /// The sha256 digest of the current version of the file, if any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub checksum: Option<String>,
+}
+
+/// The states of a configuration list request
+///
+/// The default workflow of the operation is derived from the `next` attributes of the states.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize, OperationState)]
+#[serde(tag = "status", rename_all = "kebab-case")]
+#[operation(operation = "configuration", request = "list")]
+pub enum ConfigListState {
+    #[operation(next = ["successful", "failed", "cancelled"])]
+    Init {},
+    #[operation(next = [])]
+    Successful {
+        #[serde(default)]
+        files: Vec<ConfigFileInfo>,
+    },
+    #[operation(next = [])]
+    Failed {
+        #[serde(default)]
+        reason: String,
+    },
+    #[operation(next = [])]
+    Cancelled {
+        #[serde(default)]
+        reason: String,
+    },
+}
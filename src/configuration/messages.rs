@@ -1,174 +1,188 @@
-use crate::operations_sm::messages::OperationPluginMessage;
 use serde::Deserialize;
 use serde::Serialize;
+use tedge_mqtt_state_machine_macros::OperationState;
 
 #[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
 pub struct ConfigUpdateRequest {
     /// The target of the new configuration
-    target: String,
+    pub target: String,
 
     /// The url from where the new configuration has to be downloaded
-    src_url: String,
+    pub src_url: String,
 
-    /// The checksum to control the integrity of the configuration
-    sha256: String,
+    /// The checksum to control the integrity of the configuration, if any
+    #[serde(default)]
+    pub sha256: String,
 }
 
-#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
-pub enum ConfigUpdateRequestState {
+/// The states of a configuration update request
+///
+/// The states are tagged by the `status` of the operation payload,
+/// the request fields and the state specific fields being at the same level.
+/// The state specific fields are optional, as the states might be produced
+/// by the user-provided steps of a custom workflow.
+///
+/// The default workflow of the operation is derived from the `next` attributes of the states.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize, OperationState)]
+#[serde(tag = "status", rename_all = "kebab-case")]
+#[operation(operation = "configuration", request = "update")]
+pub enum ConfigUpdateState {
+    #[operation(next = ["scheduled", "failed", "cancelled"])]
     Init {
-        id: String,
+        #[serde(flatten)]
         request: ConfigUpdateRequest,
     },
+    #[operation(next = ["downloading", "cancelled"])]
     Scheduled {
-        id: String,
+        #[serde(flatten)]
         request: ConfigUpdateRequest,
     },
+    #[operation(next = ["downloaded", "failed", "cancelled"])]
     Downloading {
-        id: String,
+        #[serde(flatten)]
         request: ConfigUpdateRequest,
+        #[serde(default)]
         path: String,
     },
+    #[operation(next = ["installing", "failed", "cancelled"])]
     Downloaded {
-        id: String,
+        #[serde(flatten)]
         request: ConfigUpdateRequest,
+        #[serde(default)]
         path: String,
+        /// The sha256 digest of the downloaded file
+        #[serde(default)]
+        checksum: String,
     },
+    #[operation(next = ["successful", "failed", "rolled-back"])]
     Installing {
-        id: String,
+        #[serde(flatten)]
         request: ConfigUpdateRequest,
+        #[serde(default)]
         path: String,
     },
+    #[operation(next = [])]
     Successful {
-        id: String,
+        #[serde(flatten)]
         request: ConfigUpdateRequest,
     },
+    #[operation(next = [])]
     Failed {
-        id: String,
+        #[serde(flatten)]
         request: ConfigUpdateRequest,
+        #[serde(default)]
         reason: String,
     },
-    InvalidState {
-        id: String,
-        error: String,
+    #[operation(next = [])]
+    Cancelled {
+        #[serde(flatten)]
+        request: ConfigUpdateRequest,
+        #[serde(default)]
+        reason: String,
+    },
+    #[operation(next = [])]
+    RolledBack {
+        #[serde(flatten)]
+        request: ConfigUpdateRequest,
+        #[serde(default)]
+        reason: String,
     },
 }
 
-impl From<OperationPluginMessage> for ConfigUpdateRequestState {
-    fn from(message: OperationPluginMessage) -> Self {
-        let key = &message.operation;
-        let id: String = key.into();
-        let path = message
-            .json
-            .get("path")
-            .and_then(|v| v.as_str())
-            .unwrap_or("")
-            .to_string();
-        let reason = message
-            .json
-            .get("error")
-            .and_then(|v| v.as_str())
-            .unwrap_or("")
-            .to_string();
-        let request = match serde_json::from_value(message.json) {
-            Ok(request) => request,
-            Err(err) => {
-                return ConfigUpdateRequestState::InvalidState {
-                    id,
-                    error: format!("Invalid configuration update state: {err}"),
-                }
-            }
-        };
-        match message.status.as_str() {
-            "init" => ConfigUpdateRequestState::Init { id, request },
-            "scheduled" => ConfigUpdateRequestState::Scheduled { id, request },
-            "downloading" => ConfigUpdateRequestState::Downloading { id, request, path },
-            "downloaded" => ConfigUpdateRequestState::Downloaded { id, request, path },
-            "installing" => ConfigUpdateRequestState::Installing { id, request, path },
-            "successful" => ConfigUpdateRequestState::Successful { id, request },
-            "failed" => ConfigUpdateRequestState::Failed {
-                id,
-                request,
-                reason,
-            },
-            unknown => {
-                return ConfigUpdateRequestState::InvalidState {
-                    id,
-                    error: format!("Invalid configuration update state: unknown status: {unknown}"),
-                }
-            }
-        }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::operations_sm::config::OperationKey;
+    use crate::operations_sm::messages::OperationPluginMessage;
+    use crate::operations_sm::plugin::{message_to_state, state_to_message};
+    use serde_json::{json, Value};
+
+    fn operation() -> OperationKey {
+        OperationKey::try_from(&"tedge/operations/main-device/configuration/update/123".to_string())
+            .unwrap()
     }
-}
 
-impl From<ConfigUpdateRequestState> for OperationPluginMessage {
-    fn from(state: ConfigUpdateRequestState) -> Self {
-        let operation = state.id().try_into().expect("A valid topic");
-        let status = state.status().to_string();
-        let path = state.path();
-        let json = match state.request() {
-            Some(request) => {
-                let mut json = serde_json::to_value(request).unwrap();
-                if let Some(path) = path {
-                    json.as_object_mut()
-                        .map(|o| o.insert("path".to_string(), path.into()));
-                };
-                json
-            }
-            None => serde_json::to_value(&format!(r#"{{ "status":"invalid" }}"#)).unwrap(),
-        };
+    fn message(json: Value) -> OperationPluginMessage {
+        let status = json["status"].as_str().unwrap_or_default().to_string();
+        OperationPluginMessage {
+            operation: operation(),
+            status,
+            json,
+        }
+    }
 
-        OperationPluginMessage::new(operation, status, json)
+    /// Convert a message into a state and back, checking the payload is unchanged
+    fn round_trip(json: Value) -> ConfigUpdateState {
+        let state: ConfigUpdateState = message_to_state(&message(json.clone())).unwrap();
+        let message = state_to_message(operation(), &state).unwrap();
+        assert_eq!(message.json, json);
+        assert_eq!(message.status, state.status());
+        state
     }
-}
 
-impl ConfigUpdateRequestState {
-    pub fn status(&self) -> &'static str {
-        match self {
-            ConfigUpdateRequestState::Init { .. } => "init",
-            ConfigUpdateRequestState::Scheduled { .. } => "scheduled",
-            ConfigUpdateRequestState::Downloading { .. } => "downloading",
-            ConfigUpdateRequestState::Downloaded { .. } => "downloaded",
-            ConfigUpdateRequestState::Installing { .. } => "installing",
-            ConfigUpdateRequestState::Successful { .. } => "successful",
-            ConfigUpdateRequestState::Failed { .. } => "failed",
-            ConfigUpdateRequestState::InvalidState { .. } => "invalid",
+    fn payload(status: &str, fields: Value) -> Value {
+        let mut json = json!({
+            "status": status,
+            "target": "mosquitto",
+            "src_url": "http://localhost:8000/mosquitto.conf",
+            "sha256": "66653d24",
+        });
+        for (key, value) in fields.as_object().unwrap() {
+            json[key] = value.clone();
         }
+        json
     }
-    pub fn request(&self) -> Option<&ConfigUpdateRequest> {
-        match self {
-            ConfigUpdateRequestState::Init { request, .. }
-            | ConfigUpdateRequestState::Scheduled { request, .. }
-            | ConfigUpdateRequestState::Downloading { request, .. }
-            | ConfigUpdateRequestState::Downloaded { request, .. }
-            | ConfigUpdateRequestState::Installing { request, .. }
-            | ConfigUpdateRequestState::Successful { request, .. }
-            | ConfigUpdateRequestState::Failed { request, .. } => Some(request),
-            ConfigUpdateRequestState::InvalidState { .. } => None,
+
+    #[test]
+    fn round_trip_all_the_states() {
+        for status in ["init", "scheduled", "successful"] {
+            round_trip(payload(status, json!({})));
         }
-    }
-    pub fn path(&self) -> Option<String> {
-        match self {
-            ConfigUpdateRequestState::Downloading { path, .. }
-            | ConfigUpdateRequestState::Downloaded { path, .. }
-            | ConfigUpdateRequestState::Installing { path, .. } => Some(path.to_string()),
-            ConfigUpdateRequestState::Init { .. }
-            | ConfigUpdateRequestState::Scheduled { .. }
-            | ConfigUpdateRequestState::Successful { .. }
-            | ConfigUpdateRequestState::Failed { .. }
-            | ConfigUpdateRequestState::InvalidState { .. } => None,
+        for status in ["downloading", "installing"] {
+            let state = round_trip(payload(status, json!({"path": "/tmp/config"})));
+            assert_eq!(state.path().map(String::as_str), Some("/tmp/config"));
         }
-    }
-    pub fn id(&self) -> &String {
-        match self {
-            ConfigUpdateRequestState::Init { id, .. }
-            | ConfigUpdateRequestState::Scheduled { id, .. }
-            | ConfigUpdateRequestState::Downloading { id, .. }
-            | ConfigUpdateRequestState::Downloaded { id, .. }
-            | ConfigUpdateRequestState::Installing { id, .. }
-            | ConfigUpdateRequestState::Successful { id, .. }
-            | ConfigUpdateRequestState::Failed { id, .. }
-            | ConfigUpdateRequestState::InvalidState { id, .. } => id,
+        for status in ["failed", "cancelled", "rolled-back"] {
+            let state = round_trip(payload(status, json!({"reason": "Oops"})));
+            assert_eq!(state.reason().map(String::as_str), Some("Oops"));
         }
+        let state = round_trip(payload(
+            "downloaded",
+            json!({"path": "/tmp/config", "checksum": "66653d24"}),
+        ));
+        assert_eq!(state.path().map(String::as_str), Some("/tmp/config"));
+        assert_eq!(state.checksum().map(String::as_str), Some("66653d24"));
+    }
+
+    #[test]
+    fn the_statuses_are_kebab_cased() {
+        let state = ConfigUpdateState::RolledBack {
+            request: ConfigUpdateRequest::default(),
+            reason: "Oops".to_string(),
+        };
+        let message = state_to_message(operation(), &state).unwrap();
+        assert_eq!(message.status, "rolled-back");
+        assert_eq!(message.json["status"], "rolled-back");
+        assert_eq!(state.status(), "rolled-back");
+    }
+
+    #[test]
+    fn the_request_checksum_is_optional() {
+        let json =
+            json!({"status": "init", "target": "mosquitto", "src_url": "file:///tmp/config"});
+        let state: ConfigUpdateState = message_to_state(&message(json)).unwrap();
+        assert_eq!(state.request().sha256, "");
+    }
+
+    #[test]
+    fn reject_an_unknown_status() {
+        let json = payload("unknown", json!({}));
+        assert!(message_to_state::<ConfigUpdateState>(&message(json)).is_err());
+    }
+
+    #[test]
+    fn reject_a_state_without_request() {
+        let json = json!({"status": "init", "src_url": "file:///tmp/config"});
+        assert!(message_to_state::<ConfigUpdateState>(&message(json)).is_err());
     }
 }
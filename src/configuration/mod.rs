@@ -1,3 +1,9 @@
 pub mod actor;
-pub mod builder;
+pub mod config;
+pub mod download;
+pub mod file_transfer;
+pub mod install;
+pub mod list;
 pub mod messages;
+pub mod snapshot;
+pub mod upload;
@@ -0,0 +1,201 @@
+use crate::configuration::config::{ConfigPluginConfig, ConfigTarget};
+use crate::configuration::snapshot::messages::{ConfigSnapshotRequest, ConfigSnapshotState};
+use crate::configuration::upload::upload;
+use crate::operations_sm::config::OperationKey;
+use crate::operations_sm::plugin::{OperationPlugin, OperationPluginTasks};
+
+/// Plugin that handles the configuration snapshot requests.
+pub struct ConfigSnapshotManager {
+    /// The configuration files managed by this plugin
+    config: ConfigPluginConfig,
+}
+
+impl OperationPlugin for ConfigSnapshotManager {
+    type State = ConfigSnapshotState;
+
+    const HANDLED_STATES: &'static [&'static str] = &["init", "uploading"];
+
+    fn name(&self) -> &str {
+        "ConfigurationSnapshotManager"
+    }
+
+    /// Process a new state of a snapshot request.
+    ///
+    /// The upload being started on the `uploading` state, and not on the `init` state,
+    /// an operation found on start in a state owned by this plugin is simply processed again.
+    fn update(
+        &mut self,
+        operation: &OperationKey,
+        state: ConfigSnapshotState,
+        tasks: &mut OperationPluginTasks<ConfigSnapshotState>,
+    ) -> Option<ConfigSnapshotState> {
+        match state {
+            ConfigSnapshotState::Init { request } => Some(self.init(request)),
+            ConfigSnapshotState::Uploading { request } => {
+                self.start_upload(operation, request, tasks)
+            }
+            ConfigSnapshotState::Successful { .. }
+            | ConfigSnapshotState::Failed { .. }
+            | ConfigSnapshotState::Cancelled { .. } => None,
+        }
+    }
+}
+
+impl ConfigSnapshotManager {
+    pub fn new(config: ConfigPluginConfig) -> Self {
+        ConfigSnapshotManager { config }
+    }
+
+    /// A new request is immediately moved to the upload step, unless its target is unknown.
+    fn init(&mut self, request: ConfigSnapshotRequest) -> ConfigSnapshotState {
+        match self.get_target(&request) {
+            Ok(_) => ConfigSnapshotState::Uploading { request },
+            Err(reason) => ConfigSnapshotState::Failed { request, reason },
+        }
+    }
+
+    fn get_target(&self, request: &ConfigSnapshotRequest) -> Result<&ConfigTarget, String> {
+        self.config
+            .get_target(&request.target)
+            .ok_or_else(|| format!("Unknown configuration type: {}", request.target))
+    }
+
+    /// Upload the current version of the target file in the background,
+    /// the outcome being sent when the upload completes.
+    fn start_upload(
+        &mut self,
+        operation: &OperationKey,
+        request: ConfigSnapshotRequest,
+        tasks: &mut OperationPluginTasks<ConfigSnapshotState>,
+    ) -> Option<ConfigSnapshotState> {
+        if tasks.is_pending(operation) {
+            return None;
+        }
+        let path = match self.get_target(&request) {
+            Ok(target) => target.path.clone(),
+            Err(reason) => return Some(ConfigSnapshotState::Failed { request, reason }),
+        };
+        tasks.spawn(operation, async move {
+            Some(match upload(&path, &request.dst_url).await {
+                Ok(()) => ConfigSnapshotState::Successful { request },
+                Err(reason) => ConfigSnapshotState::Failed { request, reason },
+            })
+        });
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+
+    fn operation() -> OperationKey {
+        OperationKey::try_from(&"tedge/operations/main-device/configuration/snapshot/1".to_string())
+            .unwrap()
+    }
+
+    fn manager(dir: &tempfile::TempDir) -> ConfigSnapshotManager {
+        let path = dir.path().join("mosquitto.conf");
+        std::fs::write(&path, "listener 1883").unwrap();
+        ConfigSnapshotManager::new(ConfigPluginConfig {
+            files: vec![ConfigTarget {
+                config_type: "mosquitto".to_string(),
+                path: path.to_string_lossy().to_string(),
+                user: None,
+                group: None,
+                mode: None,
+                post_install: None,
+                backups: 1,
+            }],
+            file_transfer: None,
+        })
+    }
+
+    fn request(target: &str, dst_url: &str) -> ConfigSnapshotRequest {
+        ConfigSnapshotRequest {
+            target: target.to_string(),
+            dst_url: dst_url.to_string(),
+        }
+    }
+
+    /// A local stand-in for an HTTP server, answering the first request with a raw response
+    fn http_stand_in(response: &'static str) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/snapshot", listener.local_addr().unwrap());
+        std::thread::spawn(move || {
+            if let Ok((mut stream, _)) = listener.accept() {
+                let mut request = [0; 1024];
+                let _ = stream.read(&mut request);
+                let _ = stream.write_all(response.as_bytes());
+            }
+        });
+        url
+    }
+
+    /// Move a request to the upload step and return the outcome of the upload
+    async fn snapshot(
+        manager: &mut ConfigSnapshotManager,
+        request: ConfigSnapshotRequest,
+    ) -> ConfigSnapshotState {
+        let mut tasks = OperationPluginTasks::new();
+        let init = ConfigSnapshotState::Init { request };
+        let uploading = manager.update(&operation(), init, &mut tasks).unwrap();
+        assert!(matches!(uploading, ConfigSnapshotState::Uploading { .. }));
+
+        assert_eq!(manager.update(&operation(), uploading, &mut tasks), None);
+        let (_, _, outcome) = tasks.next().await.unwrap();
+        outcome.unwrap().unwrap()
+    }
+
+    #[tokio::test]
+    async fn upload_the_current_version_of_a_configuration_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut manager = manager(&dir);
+        let url = http_stand_in("HTTP/1.1 201 Created\r\nContent-Length: 0\r\n\r\n");
+
+        let outcome = snapshot(&mut manager, request("mosquitto", &url)).await;
+
+        assert_eq!(
+            outcome,
+            ConfigSnapshotState::Successful {
+                request: request("mosquitto", &url)
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn fail_the_snapshot_when_the_upload_is_refused() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut manager = manager(&dir);
+        let url = http_stand_in("HTTP/1.1 403 Forbidden\r\nContent-Length: 0\r\n\r\n");
+
+        let outcome = snapshot(&mut manager, request("mosquitto", &url)).await;
+
+        let ConfigSnapshotState::Failed { reason, .. } = outcome else {
+            panic!("Unexpected state: {outcome:?}");
+        };
+        assert!(reason.contains("HTTP 403"), "{reason}");
+    }
+
+    #[test]
+    fn fail_the_snapshot_of_an_unknown_configuration_type() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut manager = manager(&dir);
+        let mut tasks = OperationPluginTasks::new();
+
+        let init = ConfigSnapshotState::Init {
+            request: request("unknown", "http://127.0.0.1/snapshot"),
+        };
+        let outcome = manager.update(&operation(), init, &mut tasks);
+
+        assert_eq!(
+            outcome,
+            Some(ConfigSnapshotState::Failed {
+                request: request("unknown", "http://127.0.0.1/snapshot"),
+                reason: "Unknown configuration type: unknown".to_string(),
+            })
+        );
+    }
+}
@@ -0,0 +1,51 @@
+use serde::Deserialize;
+use serde::Serialize;
+use tedge_mqtt_state_machine_macros::OperationState;
+
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct ConfigSnapshotRequest {
+    /// The target of the configuration snapshot
+    pub target: String,
+
+    /// The url where the configuration has to be uploaded
+    pub dst_url: String,
+}
+
+/// The states of a configuration snapshot request
+///
+/// The default workflow of the operation is derived from the `next` attributes of the states.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize, OperationState)]
+#[serde(tag = "status", rename_all = "kebab-case")]
+#[operation(operation = "configuration", request = "snapshot")]
+pub enum ConfigSnapshotState {
+    /// The default behavior is to immediately upload the requested configuration file.
+    #[operation(next = ["uploading", "failed", "cancelled"])]
+    Init {
+        #[serde(flatten)]
+        request: ConfigSnapshotRequest,
+    },
+    #[operation(next = ["successful", "failed", "cancelled"])]
+    Uploading {
+        #[serde(flatten)]
+        request: ConfigSnapshotRequest,
+    },
+    #[operation(next = [])]
+    Successful {
+        #[serde(flatten)]
+        request: ConfigSnapshotRequest,
+    },
+    #[operation(next = [])]
+    Failed {
+        #[serde(flatten)]
+        request: ConfigSnapshotRequest,
+        #[serde(default)]
+        reason: String,
+    },
+    #[operation(next = [])]
+    Cancelled {
+        #[serde(flatten)]
+        request: ConfigSnapshotRequest,
+        #[serde(default)]
+        reason: String,
+    },
+}
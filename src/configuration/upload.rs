@@ -0,0 +1,143 @@
+use crate::configuration::download::{http_client, READ_TIMEOUT};
+use std::time::Duration;
+
+/// Upload the content of a file to an `http://` or `https://` url, using a PUT request
+pub async fn upload(path: &str, dst_url: &str) -> Result<(), String> {
+    if !dst_url.starts_with("http://") && !dst_url.starts_with("https://") {
+        return Err(format!("Unsupported url scheme: {dst_url}"));
+    }
+    put_http(path, dst_url, READ_TIMEOUT).await
+}
+
+/// Send the content of a file to an HTTP url,
+/// failing if the file is not sent and the response received within `timeout`
+async fn put_http(path: &str, dst_url: &str, timeout: Duration) -> Result<(), String> {
+    let content = tokio::fs::read(path)
+        .await
+        .map_err(|err| format!("Fail to read {path}: {err}"))?;
+
+    let request = http_client()?.put(dst_url).body(content).send();
+    let response = tokio::time::timeout(timeout, request)
+        .await
+        .map_err(|_| format!("Fail to upload {path} to {dst_url}: timeout after {timeout:?}"))?
+        .map_err(|err| format!("Fail to connect to {dst_url}: {err}"))?;
+
+    let status = response.status();
+    if !status.is_success() {
+        return Err(format!("Fail to upload {path} to {dst_url}: HTTP {status}"));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::sync::mpsc;
+
+    /// A local stand-in for an HTTP server,
+    /// forwarding the first request it receives and answering it with a raw response, if any
+    fn http_stand_in(response: Option<&'static str>) -> (String, mpsc::Receiver<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/snapshot", listener.local_addr().unwrap());
+        let (sender, receiver) = mpsc::channel();
+        std::thread::spawn(move || {
+            if let Ok((mut stream, _)) = listener.accept() {
+                let request = read_request(&mut stream);
+                let _ = sender.send(request);
+                if let Some(response) = response {
+                    let _ = stream.write_all(response.as_bytes());
+                }
+                std::thread::sleep(Duration::from_secs(5));
+            }
+        });
+        (url, receiver)
+    }
+
+    /// Read a request up to the end of its body, as given by its content length
+    fn read_request(stream: &mut impl Read) -> String {
+        let mut request = vec![];
+        let mut buffer = [0; 1024];
+        loop {
+            let n = stream.read(&mut buffer).unwrap_or(0);
+            if n == 0 {
+                break;
+            }
+            request.extend_from_slice(&buffer[..n]);
+            let text = String::from_utf8_lossy(&request).to_string();
+            if let Some((headers, body)) = text.split_once("\r\n\r\n") {
+                let length = headers
+                    .lines()
+                    .find_map(|line| {
+                        line.to_lowercase()
+                            .strip_prefix("content-length:")
+                            .map(|length| length.trim().parse::<usize>().unwrap_or(0))
+                    })
+                    .unwrap_or(0);
+                if body.len() >= length {
+                    break;
+                }
+            }
+        }
+        String::from_utf8_lossy(&request).to_string()
+    }
+
+    fn source_file(dir: &tempfile::TempDir) -> String {
+        let path = dir.path().join("mosquitto.conf");
+        std::fs::write(&path, "listener 1883").unwrap();
+        path.to_string_lossy().to_string()
+    }
+
+    #[tokio::test]
+    async fn upload_a_file_over_http() {
+        let (url, requests) =
+            http_stand_in(Some("HTTP/1.1 201 Created\r\nContent-Length: 0\r\n\r\n"));
+        let dir = tempfile::tempdir().unwrap();
+        let path = source_file(&dir);
+
+        upload(&path, &url).await.unwrap();
+
+        let request = requests.recv().unwrap();
+        assert!(request.starts_with("PUT /snapshot "), "{request}");
+        assert!(request.ends_with("\r\n\r\nlistener 1883"), "{request}");
+    }
+
+    #[tokio::test]
+    async fn report_http_errors() {
+        let (url, _) = http_stand_in(Some(
+            "HTTP/1.1 500 Internal Server Error\r\nContent-Length: 0\r\n\r\n",
+        ));
+        let dir = tempfile::tempdir().unwrap();
+        let path = source_file(&dir);
+
+        let err = upload(&path, &url).await.unwrap_err();
+
+        assert!(err.contains("HTTP 500"), "{err}");
+    }
+
+    #[tokio::test]
+    async fn give_up_on_a_server_that_never_responds() {
+        let (url, _) = http_stand_in(None);
+        let dir = tempfile::tempdir().unwrap();
+        let path = source_file(&dir);
+
+        let err = put_http(&path, &url, Duration::from_millis(200))
+            .await
+            .unwrap_err();
+
+        assert!(err.contains("timeout"), "{err}");
+    }
+
+    #[tokio::test]
+    async fn reject_the_unsupported_urls() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = source_file(&dir);
+
+        let err = upload(&path, "ftp://192.168.1.1/snapshot")
+            .await
+            .unwrap_err();
+
+        assert!(err.contains("Unsupported url scheme"), "{err}");
+    }
+}
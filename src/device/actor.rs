@@ -0,0 +1,204 @@
+use crate::device::config::DevicePluginConfig;
+use crate::device::messages::DeviceRestartState;
+use crate::device::restart::RestartMarkers;
+use crate::operations_sm::config::OperationKey;
+use crate::operations_sm::plugin::{OperationPlugin, OperationPluginTasks};
+use crate::operations_sm::script::run_command;
+
+/// Plugin that handles the device restart requests.
+///
+/// Before restarting the device, a marker is persisted with the current boot id.
+/// On start, the `restarting` state being found retained, the plugin checks
+/// that the boot id actually changed to conclude the operation.
+pub struct DeviceManager {
+    config: DevicePluginConfig,
+
+    /// The markers of the pending restarts
+    markers: RestartMarkers,
+}
+
+impl OperationPlugin for DeviceManager {
+    type State = DeviceRestartState;
+
+    const HANDLED_STATES: &'static [&'static str] = &["init", "scheduled", "restarting"];
+
+    fn name(&self) -> &str {
+        "DeviceManager"
+    }
+
+    fn update(
+        &mut self,
+        operation: &OperationKey,
+        state: DeviceRestartState,
+        tasks: &mut OperationPluginTasks<DeviceRestartState>,
+    ) -> Option<DeviceRestartState> {
+        match state {
+            DeviceRestartState::Init {} => Some(DeviceRestartState::Scheduled {}),
+            DeviceRestartState::Scheduled {} => Some(DeviceRestartState::Restarting {}),
+            DeviceRestartState::Restarting {} => self.start_restart(operation, tasks),
+            DeviceRestartState::Successful {}
+            | DeviceRestartState::Failed { .. }
+            | DeviceRestartState::Cancelled { .. } => None,
+        }
+    }
+
+    /// Resume an operation found on start in a state owned by this plugin.
+    ///
+    /// An operation found `restarting` is concluded using the marker persisted before the restart:
+    /// - the operation is `successful` if the boot id changed,
+    /// - the operation is `failed` if the boot id is unchanged (only the daemon has been restarted),
+    /// - the restart is triggered again if there is no marker (the daemon stopped before the restart).
+    fn recover(
+        &mut self,
+        operation: &OperationKey,
+        state: DeviceRestartState,
+        tasks: &mut OperationPluginTasks<DeviceRestartState>,
+    ) -> Option<DeviceRestartState> {
+        match state {
+            DeviceRestartState::Restarting {} => {
+                let id = String::from(operation);
+                match self.markers.check(&id) {
+                    Some(Ok(())) => Some(DeviceRestartState::Successful {}),
+                    Some(Err(reason)) => Some(DeviceRestartState::Failed { reason }),
+                    None => {
+                        log::info!("Restart the device for {id}, the restart being interrupted");
+                        self.start_restart(operation, tasks)
+                    }
+                }
+            }
+            state => self.update(operation, state, tasks),
+        }
+    }
+}
+
+impl DeviceManager {
+    pub fn new(config: DevicePluginConfig) -> Self {
+        let markers = RestartMarkers::new(&config.marker);
+        DeviceManager { config, markers }
+    }
+
+    /// Persist the restart marker, then restart the device.
+    ///
+    /// Nothing is sent on success, the workflow being concluded after the restart.
+    fn start_restart(
+        &mut self,
+        operation: &OperationKey,
+        tasks: &mut OperationPluginTasks<DeviceRestartState>,
+    ) -> Option<DeviceRestartState> {
+        let id = String::from(operation);
+        if let Err(reason) = self.markers.write(&id) {
+            return Some(DeviceRestartState::Failed { reason });
+        }
+        let restart = self.config.restart.clone();
+        let markers = self.markers.clone();
+        tasks.spawn(operation, async move {
+            log::info!("Restart the device for {id}");
+            match run_command(&restart).await {
+                Ok(_) => None,
+                Err(reason) => {
+                    markers.remove(&id);
+                    Some(DeviceRestartState::Failed { reason })
+                }
+            }
+        });
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn operation(instance: &str) -> OperationKey {
+        OperationKey::try_from(&format!(
+            "tedge/operations/main-device/device/restart/{instance}"
+        ))
+        .unwrap()
+    }
+
+    /// A device manager whose restart command does nothing, the boot id being faked
+    fn device_manager(dir: &TempDir) -> DeviceManager {
+        let boot_id = dir.path().join("boot_id");
+        let marker = dir.path().join("restart.marker");
+        let mut manager = DeviceManager::new(DevicePluginConfig {
+            restart: "true".to_string(),
+            marker: marker.to_string_lossy().to_string(),
+        });
+        manager.markers = manager
+            .markers
+            .clone()
+            .with_boot_id_path(&boot_id.to_string_lossy());
+        manager
+    }
+
+    fn boot(dir: &TempDir, boot_id: &str) {
+        std::fs::write(dir.path().join("boot_id"), boot_id).unwrap();
+    }
+
+    #[tokio::test]
+    async fn conclude_all_the_restart_operations_after_a_reboot() {
+        let dir = tempfile::tempdir().unwrap();
+        let (first, second) = (operation("1"), operation("2"));
+        let restarting = DeviceRestartState::Restarting {};
+
+        boot(&dir, "boot-1");
+        let mut manager = device_manager(&dir);
+        let mut tasks = OperationPluginTasks::new();
+        assert_eq!(manager.update(&first, restarting.clone(), &mut tasks), None);
+        assert_eq!(
+            manager.update(&second, restarting.clone(), &mut tasks),
+            None
+        );
+
+        boot(&dir, "boot-2");
+        let mut manager = device_manager(&dir);
+        let mut tasks = OperationPluginTasks::new();
+        assert_eq!(
+            manager.recover(&first, restarting.clone(), &mut tasks),
+            Some(DeviceRestartState::Successful {})
+        );
+        assert_eq!(
+            manager.recover(&second, restarting, &mut tasks),
+            Some(DeviceRestartState::Successful {})
+        );
+        assert!(!tasks.is_pending(&first));
+        assert!(!tasks.is_pending(&second));
+    }
+
+    #[tokio::test]
+    async fn fail_a_restart_operation_when_the_device_did_not_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let restarting = DeviceRestartState::Restarting {};
+
+        boot(&dir, "boot-1");
+        let mut manager = device_manager(&dir);
+        let mut tasks = OperationPluginTasks::new();
+        manager.update(&operation("1"), restarting.clone(), &mut tasks);
+
+        let mut manager = device_manager(&dir);
+        let mut tasks = OperationPluginTasks::new();
+        assert!(matches!(
+            manager.recover(&operation("1"), restarting, &mut tasks),
+            Some(DeviceRestartState::Failed { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn restart_again_when_the_restart_has_been_interrupted() {
+        let dir = tempfile::tempdir().unwrap();
+        let restarting = DeviceRestartState::Restarting {};
+
+        boot(&dir, "boot-1");
+        let mut manager = device_manager(&dir);
+        let mut tasks = OperationPluginTasks::new();
+        assert_eq!(
+            manager.recover(&operation("1"), restarting, &mut tasks),
+            None
+        );
+        assert!(tasks.is_pending(&operation("1")));
+
+        let id = String::from(&operation("1"));
+        assert!(manager.markers.check(&id).is_some());
+    }
+}
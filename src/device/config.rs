@@ -0,0 +1,39 @@
+use crate::operations_sm::plugin::PluginConfig;
+use serde::{Deserialize, Serialize};
+
+/// The configuration of the device plugin
+///
+/// ```toml
+/// restart = "sudo reboot"
+/// marker = "./journal/restart.marker"
+/// ```
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DevicePluginConfig {
+    /// The command restarting the device
+    #[serde(default = "default_restart")]
+    pub restart: String,
+
+    /// The path of the files where are persisted the pending restarts, to be checked on start,
+    /// suffixed by the id of the operation that triggered the restart
+    #[serde(default = "default_marker")]
+    pub marker: String,
+}
+
+impl Default for DevicePluginConfig {
+    fn default() -> Self {
+        DevicePluginConfig {
+            restart: default_restart(),
+            marker: default_marker(),
+        }
+    }
+}
+
+fn default_restart() -> String {
+    "sudo reboot".to_string()
+}
+
+fn default_marker() -> String {
+    "./journal/restart.marker".to_string()
+}
+
+impl PluginConfig for DevicePluginConfig {}
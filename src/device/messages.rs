@@ -0,0 +1,33 @@
+use serde::Deserialize;
+use serde::Serialize;
+use tedge_mqtt_state_machine_macros::OperationState;
+
+/// The states of a device restart request
+///
+/// The default workflow of the operation is derived from the `next` attributes of the states.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize, OperationState)]
+#[serde(tag = "status", rename_all = "kebab-case")]
+#[operation(operation = "device", request = "restart")]
+pub enum DeviceRestartState {
+    /// The default behavior is to immediately schedule the new request.
+    #[operation(next = ["scheduled", "failed", "cancelled"])]
+    Init {},
+    #[operation(next = ["restarting", "cancelled"])]
+    Scheduled {},
+    /// The device is restarted while in this state, which can not be cancelled.
+    /// The daemon concludes the workflow on start, when this state is found retained.
+    #[operation(next = ["successful", "failed"])]
+    Restarting {},
+    #[operation(next = [])]
+    Successful {},
+    #[operation(next = [])]
+    Failed {
+        #[serde(default)]
+        reason: String,
+    },
+    #[operation(next = [])]
+    Cancelled {
+        #[serde(default)]
+        reason: String,
+    },
+}
@@ -0,0 +1,4 @@
+pub mod actor;
+pub mod config;
+pub mod messages;
+pub mod restart;
@@ -0,0 +1,181 @@
+use serde::Deserialize;
+use serde::Serialize;
+use std::path::Path;
+
+/// Where the kernel exposes the id of the current boot
+const BOOT_ID_PATH: &str = "/proc/sys/kernel/random/boot_id";
+
+/// The marker persisted before a restart
+///
+/// A restart is known to have actually happened when the boot id of the device
+/// is no more the one recorded along the pending operation.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct RestartMarker {
+    /// The id of the operation that triggered the restart
+    pub operation: String,
+
+    /// The boot id of the device before the restart
+    pub boot_id: String,
+}
+
+/// The restart markers of a plugin, one per operation
+///
+/// Each pending operation has its own marker, so an operation concluded after a restart
+/// doesn't prevent the other pending operations to be concluded too.
+#[derive(Clone, Debug)]
+pub struct RestartMarkers {
+    /// The path of the markers, suffixed by the operation ids
+    path: String,
+
+    /// The file giving the id of the current boot
+    boot_id_path: String,
+}
+
+impl RestartMarkers {
+    pub fn new(path: &str) -> Self {
+        RestartMarkers {
+            path: path.to_string(),
+            boot_id_path: BOOT_ID_PATH.to_string(),
+        }
+    }
+
+    /// Read the boot id from another file than the one exposed by the kernel
+    pub fn with_boot_id_path(self, boot_id_path: &str) -> Self {
+        RestartMarkers {
+            boot_id_path: boot_id_path.to_string(),
+            ..self
+        }
+    }
+
+    /// Persist a marker for an operation, recording the current boot id
+    pub fn write(&self, operation: &str) -> Result<(), String> {
+        let path = self.marker_path(operation);
+        let marker = RestartMarker {
+            operation: operation.to_string(),
+            boot_id: self.boot_id()?,
+        };
+        if let Some(dir) = Path::new(&path).parent() {
+            std::fs::create_dir_all(dir)
+                .map_err(|err| format!("Fail to create the directory of {path}: {err}"))?;
+        }
+        let content = serde_json::to_string(&marker)
+            .map_err(|err| format!("Fail to serialize the restart marker: {err}"))?;
+        std::fs::write(&path, content)
+            .map_err(|err| format!("Fail to persist the restart marker {path}: {err}"))
+    }
+
+    /// Check if the device has been restarted since the marker of an operation has been persisted.
+    ///
+    /// Return `None` if there is no marker for this operation, i.e. if the restart has been interrupted,
+    /// and an error if the boot id is unchanged, i.e. if only the daemon has been restarted.
+    /// The marker is removed once checked.
+    pub fn check(&self, operation: &str) -> Option<Result<(), String>> {
+        let path = self.marker_path(operation);
+        let content = std::fs::read_to_string(&path).ok()?;
+        let marker: RestartMarker = serde_json::from_str(&content).ok()?;
+        if marker.operation != operation {
+            return None;
+        }
+        self.remove(operation);
+        Some(match self.boot_id() {
+            Ok(boot_id) if boot_id != marker.boot_id => Ok(()),
+            Ok(_) => Err("The device has not been restarted".to_string()),
+            Err(reason) => Err(reason),
+        })
+    }
+
+    /// Remove the marker of an operation, if any
+    pub fn remove(&self, operation: &str) {
+        let _ = std::fs::remove_file(self.marker_path(operation));
+    }
+
+    /// The marker of an operation, named after the operation id
+    fn marker_path(&self, operation: &str) -> String {
+        format!("{}.{}", self.path, operation.replace('/', "."))
+    }
+
+    /// The id of the current boot of the device
+    fn boot_id(&self) -> Result<String, String> {
+        let path = &self.boot_id_path;
+        std::fs::read_to_string(path)
+            .map(|boot_id| boot_id.trim().to_string())
+            .map_err(|err| format!("Fail to read the boot id from {path}: {err}"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OPERATION: &str = "tedge/operations/main-device/device/restart/1";
+
+    /// Markers persisted in a temporary directory, along a fake boot id
+    fn markers(dir: &tempfile::TempDir) -> RestartMarkers {
+        let boot_id = dir.path().join("boot_id");
+        std::fs::write(&boot_id, "boot-1\n").unwrap();
+        let path = dir.path().join("journal").join("restart.marker");
+        RestartMarkers::new(&path.to_string_lossy()).with_boot_id_path(&boot_id.to_string_lossy())
+    }
+
+    fn reboot(dir: &tempfile::TempDir) {
+        std::fs::write(dir.path().join("boot_id"), "boot-2\n").unwrap();
+    }
+
+    #[test]
+    fn conclude_a_restart_when_the_boot_id_changed() {
+        let dir = tempfile::tempdir().unwrap();
+        let markers = markers(&dir);
+
+        markers.write(OPERATION).unwrap();
+        reboot(&dir);
+
+        assert_eq!(markers.check(OPERATION), Some(Ok(())));
+        assert_eq!(markers.check(OPERATION), None);
+    }
+
+    #[test]
+    fn reject_a_restart_when_the_boot_id_is_unchanged() {
+        let dir = tempfile::tempdir().unwrap();
+        let markers = markers(&dir);
+
+        markers.write(OPERATION).unwrap();
+
+        assert!(matches!(markers.check(OPERATION), Some(Err(_))));
+        assert_eq!(markers.check(OPERATION), None);
+    }
+
+    #[test]
+    fn recover_all_the_operations_pending_on_a_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let markers = markers(&dir);
+        let other = "tedge/operations/main-device/device/restart/2";
+
+        markers.write(OPERATION).unwrap();
+        markers.write(other).unwrap();
+        reboot(&dir);
+
+        assert_eq!(markers.check(OPERATION), Some(Ok(())));
+        assert_eq!(markers.check(other), Some(Ok(())));
+    }
+
+    #[test]
+    fn ignore_the_removed_markers() {
+        let dir = tempfile::tempdir().unwrap();
+        let markers = markers(&dir);
+
+        assert_eq!(markers.check(OPERATION), None);
+
+        markers.write(OPERATION).unwrap();
+        markers.remove(OPERATION);
+        reboot(&dir);
+        assert_eq!(markers.check(OPERATION), None);
+    }
+
+    #[test]
+    fn fail_to_write_a_marker_without_boot_id() {
+        let dir = tempfile::tempdir().unwrap();
+        let markers = markers(&dir).with_boot_id_path(&dir.path().join("none").to_string_lossy());
+
+        assert!(markers.write(OPERATION).is_err());
+    }
+}
@@ -0,0 +1,381 @@
+use crate::configuration::download::{download, sha256_digest};
+use crate::device::restart::RestartMarkers;
+use crate::firmware::config::FirmwarePluginConfig;
+use crate::firmware::messages::{FirmwareUpdateRequest, FirmwareUpdateState};
+use crate::operations_sm::config::OperationKey;
+use crate::operations_sm::plugin::{OperationPlugin, OperationPluginTasks};
+use crate::operations_sm::script::run_command;
+
+/// Plugin that handles the firmware update requests.
+///
+/// The device being restarted in the middle of the workflow,
+/// the `restarting` state is expected to be found retained on start.
+/// The plugin then checks, using the marker persisted before the restart,
+/// that the device has actually been restarted before verifying the new firmware.
+pub struct FirmwareManager {
+    config: FirmwarePluginConfig,
+
+    /// The markers of the pending restarts
+    markers: RestartMarkers,
+}
+
+impl OperationPlugin for FirmwareManager {
+    type State = FirmwareUpdateState;
+
+    const HANDLED_STATES: &'static [&'static str] =
+        &["init", "scheduled", "installing", "restarting", "verifying"];
+
+    fn name(&self) -> &str {
+        "FirmwareManager"
+    }
+
+    fn update(
+        &mut self,
+        operation: &OperationKey,
+        state: FirmwareUpdateState,
+        tasks: &mut OperationPluginTasks<FirmwareUpdateState>,
+    ) -> Option<FirmwareUpdateState> {
+        match state {
+            FirmwareUpdateState::Init { request } => Some(self.init(request)),
+            FirmwareUpdateState::Scheduled { request } => {
+                Some(self.start_install(operation, request, tasks))
+            }
+            FirmwareUpdateState::Installing { .. } => {
+                // Nothing to do while this plugin awaits for the actual end of the installation
+                None
+            }
+            FirmwareUpdateState::Restarting { request } => {
+                self.start_restart(operation, request, tasks)
+            }
+            FirmwareUpdateState::Verifying { request } => {
+                self.start_verification(operation, request, tasks);
+                None
+            }
+            FirmwareUpdateState::Successful { .. }
+            | FirmwareUpdateState::Failed { .. }
+            | FirmwareUpdateState::Cancelled { .. } => None,
+        }
+    }
+
+    /// Resume an operation found on start in a state owned by this plugin.
+    ///
+    /// - An interrupted installation is restarted.
+    /// - An operation found `restarting` is concluded using the marker persisted before the restart:
+    ///   moved to `verifying` if the boot id changed, `failed` if unchanged,
+    ///   the restart being triggered again if there is no marker.
+    /// - All the other steps are simply processed again.
+    fn recover(
+        &mut self,
+        operation: &OperationKey,
+        state: FirmwareUpdateState,
+        tasks: &mut OperationPluginTasks<FirmwareUpdateState>,
+    ) -> Option<FirmwareUpdateState> {
+        match state {
+            FirmwareUpdateState::Installing { request } if !tasks.is_pending(operation) => {
+                log::info!(
+                    "Restart the interrupted firmware installation for {}",
+                    String::from(operation)
+                );
+                self.start_install(operation, request, tasks);
+                None
+            }
+            FirmwareUpdateState::Restarting { request } => {
+                let id = String::from(operation);
+                match self.markers.check(&id) {
+                    Some(Ok(())) => {
+                        log::info!("Resume the firmware update {id} after restart");
+                        Some(FirmwareUpdateState::Verifying { request })
+                    }
+                    Some(Err(reason)) => Some(FirmwareUpdateState::Failed { request, reason }),
+                    None => {
+                        log::info!("Restart the device for {id}, the restart being interrupted");
+                        self.start_restart(operation, request, tasks)
+                    }
+                }
+            }
+            state => self.update(operation, state, tasks),
+        }
+    }
+
+    /// Remove any firmware left over by an installation that has been interrupted
+    fn release(&mut self, operation: &OperationKey, _state: FirmwareUpdateState) {
+        let _ = std::fs::remove_file(download_path(&String::from(operation)));
+    }
+}
+
+impl FirmwareManager {
+    pub fn new(config: FirmwarePluginConfig) -> Self {
+        let markers = RestartMarkers::new(&config.marker);
+        FirmwareManager { config, markers }
+    }
+
+    /// A new request is immediately scheduled, unless the plugin is not configured.
+    fn init(&mut self, request: FirmwareUpdateRequest) -> FirmwareUpdateState {
+        let missing = [
+            ("install", &self.config.install),
+            ("restart", &self.config.restart),
+            ("version", &self.config.version),
+        ]
+        .into_iter()
+        .find(|(_, command)| command.is_none());
+        match missing {
+            None => FirmwareUpdateState::Scheduled { request },
+            Some((name, _)) => FirmwareUpdateState::Failed {
+                request,
+                reason: format!("No firmware {name} command is configured"),
+            },
+        }
+    }
+
+    /// Download and install the new firmware in the background,
+    /// moving the operation to the `restarting` state on success.
+    fn start_install(
+        &mut self,
+        operation: &OperationKey,
+        request: FirmwareUpdateRequest,
+        tasks: &mut OperationPluginTasks<FirmwareUpdateState>,
+    ) -> FirmwareUpdateState {
+        let install = self.config.install.clone().unwrap_or_default();
+        let path = download_path(&String::from(operation));
+        let task_request = request.clone();
+        tasks.spawn(operation, async move {
+            let request = task_request;
+            let result = download_and_install(&request, &path, &install).await;
+            let _ = tokio::fs::remove_file(&path).await;
+            Some(match result {
+                Ok(()) => FirmwareUpdateState::Restarting { request },
+                Err(reason) => FirmwareUpdateState::Failed { request, reason },
+            })
+        });
+        FirmwareUpdateState::Installing { request }
+    }
+
+    /// Persist the restart marker, then restart the device.
+    ///
+    /// Nothing is sent on success, the workflow being resumed after the restart.
+    fn start_restart(
+        &mut self,
+        operation: &OperationKey,
+        request: FirmwareUpdateRequest,
+        tasks: &mut OperationPluginTasks<FirmwareUpdateState>,
+    ) -> Option<FirmwareUpdateState> {
+        let id = String::from(operation);
+        if let Err(reason) = self.markers.write(&id) {
+            return Some(FirmwareUpdateState::Failed { request, reason });
+        }
+        let restart = self.config.restart.clone().unwrap_or_default();
+        let markers = self.markers.clone();
+        tasks.spawn(operation, async move {
+            log::info!("Restart the device to complete the firmware update {id}");
+            match run_command(&restart).await {
+                Ok(_) => None,
+                Err(reason) => {
+                    markers.remove(&id);
+                    Some(FirmwareUpdateState::Failed { request, reason })
+                }
+            }
+        });
+        None
+    }
+
+    /// Check that the device runs the expected firmware version,
+    /// restoring the previous firmware on failure when a rollback command is configured.
+    fn start_verification(
+        &mut self,
+        operation: &OperationKey,
+        request: FirmwareUpdateRequest,
+        tasks: &mut OperationPluginTasks<FirmwareUpdateState>,
+    ) {
+        let version = self.config.version.clone().unwrap_or_default();
+        let rollback = self.config.rollback.clone();
+        tasks.spawn(operation, async move {
+            let reason = match run_command(&version).await {
+                Ok(actual) if actual.trim() == request.version => {
+                    return Some(FirmwareUpdateState::Successful { request });
+                }
+                Ok(actual) => format!(
+                    "Firmware version mismatch: expected {}, got {}",
+                    request.version,
+                    actual.trim()
+                ),
+                Err(err) => err,
+            };
+            let reason = match rollback {
+                Some(rollback) => match run_command(&rollback).await {
+                    Ok(_) => format!("{reason}. The previous firmware has been restored"),
+                    Err(err) => format!("{reason}. {err}"),
+                },
+                None => reason,
+            };
+            Some(FirmwareUpdateState::Failed { request, reason })
+        });
+    }
+}
+
+/// Download the firmware, check its sha256 digest if one is given, and install it
+async fn download_and_install(
+    request: &FirmwareUpdateRequest,
+    path: &str,
+    install: &str,
+) -> Result<(), String> {
+    download(&request.src_url, path).await?;
+    let checksum = sha256_digest(path).await?;
+    let expected = &request.sha256;
+    if !expected.is_empty() && !expected.eq_ignore_ascii_case(&checksum) {
+        return Err(format!(
+            "Checksum mismatch for {}: expected sha256 {expected}, got {checksum}",
+            request.src_url
+        ));
+    }
+    run_command(&format!("{install} {path}")).await?;
+    Ok(())
+}
+
+/// The path where is downloaded the new firmware of an operation
+fn download_path(id: &str) -> String {
+    let file_name = format!("firmware.download.{}", id.replace('/', "."));
+    std::env::temp_dir()
+        .join(file_name)
+        .to_string_lossy()
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn operation(instance: &str) -> OperationKey {
+        OperationKey::try_from(&format!(
+            "tedge/operations/main-device/firmware/update/{instance}"
+        ))
+        .unwrap()
+    }
+
+    fn request(version: &str) -> FirmwareUpdateRequest {
+        FirmwareUpdateRequest {
+            name: "demo".to_string(),
+            version: version.to_string(),
+            src_url: "http://localhost:8000/firmware.img".to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// A firmware manager whose restart command does nothing, the boot id being faked
+    fn firmware_manager(dir: &TempDir, version: &str) -> FirmwareManager {
+        let boot_id = dir.path().join("boot_id");
+        let marker = dir.path().join("firmware.marker");
+        let mut manager = FirmwareManager::new(FirmwarePluginConfig {
+            install: Some("true".to_string()),
+            restart: Some("true".to_string()),
+            version: Some(format!("echo {version}")),
+            rollback: Some("true".to_string()),
+            marker: marker.to_string_lossy().to_string(),
+        });
+        manager.markers = manager
+            .markers
+            .clone()
+            .with_boot_id_path(&boot_id.to_string_lossy());
+        manager
+    }
+
+    fn boot(dir: &TempDir, boot_id: &str) {
+        std::fs::write(dir.path().join("boot_id"), boot_id).unwrap();
+    }
+
+    #[test]
+    fn fail_the_requests_when_the_plugin_is_not_configured() {
+        let mut manager = FirmwareManager::new(FirmwarePluginConfig::default());
+        let state = manager.init(request("1.0.1"));
+        assert_eq!(
+            state,
+            FirmwareUpdateState::Failed {
+                request: request("1.0.1"),
+                reason: "No firmware install command is configured".to_string()
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn verify_the_firmware_of_all_the_operations_pending_on_a_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let (first, second) = (operation("1"), operation("2"));
+        let restarting = FirmwareUpdateState::Restarting {
+            request: request("1.0.1"),
+        };
+
+        boot(&dir, "boot-1");
+        let mut manager = firmware_manager(&dir, "1.0.1");
+        let mut tasks = OperationPluginTasks::new();
+        assert_eq!(manager.update(&first, restarting.clone(), &mut tasks), None);
+        assert_eq!(
+            manager.update(&second, restarting.clone(), &mut tasks),
+            None
+        );
+
+        boot(&dir, "boot-2");
+        let mut manager = firmware_manager(&dir, "1.0.1");
+        let mut tasks = OperationPluginTasks::new();
+        let verifying = FirmwareUpdateState::Verifying {
+            request: request("1.0.1"),
+        };
+        assert_eq!(
+            manager.recover(&first, restarting.clone(), &mut tasks),
+            Some(verifying.clone())
+        );
+        assert_eq!(
+            manager.recover(&second, restarting, &mut tasks),
+            Some(verifying)
+        );
+    }
+
+    #[tokio::test]
+    async fn fail_the_update_when_the_device_did_not_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let restarting = FirmwareUpdateState::Restarting {
+            request: request("1.0.1"),
+        };
+
+        boot(&dir, "boot-1");
+        let mut manager = firmware_manager(&dir, "1.0.1");
+        let mut tasks = OperationPluginTasks::new();
+        manager.update(&operation("1"), restarting.clone(), &mut tasks);
+
+        let mut manager = firmware_manager(&dir, "1.0.1");
+        let mut tasks = OperationPluginTasks::new();
+        assert!(matches!(
+            manager.recover(&operation("1"), restarting, &mut tasks),
+            Some(FirmwareUpdateState::Failed { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn check_the_version_of_the_new_firmware() {
+        let dir = tempfile::tempdir().unwrap();
+        let verifying = FirmwareUpdateState::Verifying {
+            request: request("1.0.1"),
+        };
+
+        let mut manager = firmware_manager(&dir, "1.0.1");
+        let mut tasks = OperationPluginTasks::new();
+        manager.update(&operation("1"), verifying.clone(), &mut tasks);
+        let (_, _, outcome) = tasks.next().await.unwrap();
+        assert_eq!(
+            outcome,
+            Ok(Some(FirmwareUpdateState::Successful {
+                request: request("1.0.1")
+            }))
+        );
+
+        let mut manager = firmware_manager(&dir, "1.0.0");
+        manager.update(&operation("2"), verifying, &mut tasks);
+        let (_, _, outcome) = tasks.next().await.unwrap();
+        let Ok(Some(FirmwareUpdateState::Failed { reason, .. })) = outcome else {
+            panic!("Unexpected outcome: {outcome:?}");
+        };
+        assert_eq!(
+            reason,
+            "Firmware version mismatch: expected 1.0.1, got 1.0.0. The previous firmware has been restored"
+        );
+    }
+}
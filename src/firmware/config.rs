@@ -0,0 +1,52 @@
+use crate::operations_sm::plugin::PluginConfig;
+use serde::{Deserialize, Serialize};
+
+/// The configuration of the firmware plugin
+///
+/// Gives the commands used to install a new firmware, to restart the device
+/// and to check the firmware version once restarted.
+///
+/// ```toml
+/// install = "./plugins/firmware/install.sh"
+/// restart = "sudo reboot"
+/// version = "./plugins/firmware/version.sh"
+/// rollback = "./plugins/firmware/rollback.sh"
+/// marker = "./journal/firmware.marker"
+/// ```
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct FirmwarePluginConfig {
+    /// The command installing a firmware, called with the path of the downloaded firmware
+    pub install: Option<String>,
+
+    /// The command restarting the device
+    pub restart: Option<String>,
+
+    /// The command printing on stdout the version of the running firmware
+    pub version: Option<String>,
+
+    /// Possibly a command to restore the previous firmware, when the new one can not be verified
+    pub rollback: Option<String>,
+
+    /// The path of the files where are persisted the pending restarts, to be checked on start,
+    /// suffixed by the id of the operation that triggered the restart
+    #[serde(default = "default_marker")]
+    pub marker: String,
+}
+
+impl Default for FirmwarePluginConfig {
+    fn default() -> Self {
+        FirmwarePluginConfig {
+            install: None,
+            restart: None,
+            version: None,
+            rollback: None,
+            marker: default_marker(),
+        }
+    }
+}
+
+fn default_marker() -> String {
+    "./journal/firmware.marker".to_string()
+}
+
+impl PluginConfig for FirmwarePluginConfig {}
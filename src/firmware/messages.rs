@@ -0,0 +1,76 @@
+use serde::Deserialize;
+use serde::Serialize;
+use tedge_mqtt_state_machine_macros::OperationState;
+
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct FirmwareUpdateRequest {
+    /// The firmware name
+    pub name: String,
+
+    /// The firmware version, as expected to be reported by the device once restarted
+    pub version: String,
+
+    /// The url from where the firmware has to be downloaded
+    pub src_url: String,
+
+    /// The expected sha256 digest of the firmware, if any
+    #[serde(default)]
+    pub sha256: String,
+}
+
+/// The states of a firmware update request
+///
+/// The default workflow of the operation is derived from the `next` attributes of the states.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize, OperationState)]
+#[serde(tag = "status", rename_all = "kebab-case")]
+#[operation(operation = "firmware", request = "update")]
+pub enum FirmwareUpdateState {
+    /// The default behavior is to immediately schedule the new request.
+    #[operation(next = ["scheduled", "failed", "cancelled"])]
+    Init {
+        #[serde(flatten)]
+        request: FirmwareUpdateRequest,
+    },
+    #[operation(next = ["installing", "cancelled"])]
+    Scheduled {
+        #[serde(flatten)]
+        request: FirmwareUpdateRequest,
+    },
+    /// The installation, the restart of the device and the verification can not be cancelled.
+    #[operation(next = ["restarting", "failed"])]
+    Installing {
+        #[serde(flatten)]
+        request: FirmwareUpdateRequest,
+    },
+    /// The device is restarted while in this state.
+    /// The daemon resumes the workflow on start, when this state is found retained.
+    #[operation(next = ["verifying", "failed"])]
+    Restarting {
+        #[serde(flatten)]
+        request: FirmwareUpdateRequest,
+    },
+    #[operation(next = ["successful", "failed"])]
+    Verifying {
+        #[serde(flatten)]
+        request: FirmwareUpdateRequest,
+    },
+    #[operation(next = [])]
+    Successful {
+        #[serde(flatten)]
+        request: FirmwareUpdateRequest,
+    },
+    #[operation(next = [])]
+    Failed {
+        #[serde(flatten)]
+        request: FirmwareUpdateRequest,
+        #[serde(default)]
+        reason: String,
+    },
+    #[operation(next = [])]
+    Cancelled {
+        #[serde(flatten)]
+        request: FirmwareUpdateRequest,
+        #[serde(default)]
+        reason: String,
+    },
+}
@@ -0,0 +1,3 @@
+pub mod actor;
+pub mod config;
+pub mod messages;
@@ -0,0 +1,131 @@
+use crate::configuration::upload::upload;
+use crate::logs::collect::collect;
+use crate::logs::config::LogPluginConfig;
+use crate::logs::messages::{LogBundle, LogUploadRequest, LogUploadState};
+use crate::operations_sm::config::OperationKey;
+use crate::operations_sm::plugin::{OperationPlugin, OperationPluginTasks};
+
+/// Plugin that handles the log upload requests.
+pub struct LogManager {
+    /// The log files managed by this plugin
+    config: LogPluginConfig,
+}
+
+impl OperationPlugin for LogManager {
+    type State = LogUploadState;
+
+    const HANDLED_STATES: &'static [&'static str] =
+        &["init", "collecting", "collected", "uploading"];
+
+    fn name(&self) -> &str {
+        "LogManager"
+    }
+
+    /// Process a new state of a log upload request.
+    ///
+    /// The collection and the upload being started on the `collecting` and `uploading` states,
+    /// an operation found on start in a state owned by this plugin is simply processed again.
+    fn update(
+        &mut self,
+        operation: &OperationKey,
+        state: LogUploadState,
+        tasks: &mut OperationPluginTasks<LogUploadState>,
+    ) -> Option<LogUploadState> {
+        match state {
+            LogUploadState::Init { request } => Some(self.init(request)),
+            LogUploadState::Collecting { request } => {
+                self.start_collection(operation, request, tasks);
+                None
+            }
+            LogUploadState::Collected { request, bundle } => {
+                Some(LogUploadState::Uploading { request, bundle })
+            }
+            LogUploadState::Uploading { request, bundle } => {
+                self.start_upload(operation, request, bundle, tasks);
+                None
+            }
+            LogUploadState::Successful { .. }
+            | LogUploadState::Failed { .. }
+            | LogUploadState::Cancelled { .. } => None,
+        }
+    }
+
+    /// Remove the bundle of an operation that reached a terminal state
+    fn release(&mut self, operation: &OperationKey, state: LogUploadState) {
+        if let Some(bundle) = state.bundle() {
+            let _ = std::fs::remove_file(&bundle.path);
+        }
+        let _ = std::fs::remove_file(bundle_path(&String::from(operation)));
+    }
+}
+
+impl LogManager {
+    pub fn new(config: LogPluginConfig) -> Self {
+        LogManager { config }
+    }
+
+    /// A new request is immediately moved to the collection step, unless its log type is unknown.
+    fn init(&mut self, request: LogUploadRequest) -> LogUploadState {
+        if self.config.get_patterns(&request.log_type).is_empty() {
+            let reason = format!("Unknown log type: {}", request.log_type);
+            LogUploadState::Failed { request, reason }
+        } else {
+            LogUploadState::Collecting { request }
+        }
+    }
+
+    /// Collect the requested log files in the background
+    fn start_collection(
+        &mut self,
+        operation: &OperationKey,
+        request: LogUploadRequest,
+        tasks: &mut OperationPluginTasks<LogUploadState>,
+    ) {
+        if tasks.is_pending(operation) {
+            return;
+        }
+        let patterns = self.config.get_patterns(&request.log_type);
+        let path = bundle_path(&String::from(operation));
+        tasks.spawn(operation, async move {
+            let task_request = request.clone();
+            let collection =
+                tokio::task::spawn_blocking(move || collect(&patterns, &task_request, &path));
+            Some(match collection.await {
+                Ok(Ok(bundle)) => LogUploadState::Collected { request, bundle },
+                Ok(Err(reason)) => LogUploadState::Failed { request, reason },
+                Err(err) => LogUploadState::Failed {
+                    request,
+                    reason: format!("Fail to collect the log files: {err}"),
+                },
+            })
+        });
+    }
+
+    /// Upload the collected log files in the background
+    fn start_upload(
+        &mut self,
+        operation: &OperationKey,
+        request: LogUploadRequest,
+        bundle: LogBundle,
+        tasks: &mut OperationPluginTasks<LogUploadState>,
+    ) {
+        if tasks.is_pending(operation) {
+            return;
+        }
+        tasks.spawn(operation, async move {
+            Some(match upload(&bundle.path, &request.dst_url).await {
+                Ok(()) => LogUploadState::Successful { request, bundle },
+                Err(reason) => LogUploadState::Failed { request, reason },
+            })
+        });
+    }
+}
+
+/// The path where are collected the log files of an operation
+fn bundle_path(id: &str) -> String {
+    let file_name = format!("log.bundle.{}", id.replace('/', "."));
+    std::env::temp_dir()
+        .join(file_name)
+        .to_string_lossy()
+        .to_string()
+}
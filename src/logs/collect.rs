@@ -0,0 +1,295 @@
+use crate::logs::messages::{LogBundle, LogUploadRequest};
+use std::collections::VecDeque;
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+use time::format_description::well_known::Rfc3339;
+use time::{Date, Month, OffsetDateTime, PrimitiveDateTime, Time};
+
+/// Collect into a single file the log lines matching the request filters.
+///
+/// - The log files are those matching the glob patterns, read line by line.
+/// - Only the lines logged in the requested date range are kept (see [line_timestamp]),
+///   the files last modified before this range being skipped.
+/// - Only the lines containing the requested text, and at most the last `max_lines`, are kept.
+/// - The content of each file is preceded in the bundle by a `==> {path} <==` header.
+pub fn collect(
+    patterns: &[String],
+    request: &LogUploadRequest,
+    bundle_path: &str,
+) -> Result<LogBundle, String> {
+    let date_from = parse_date("date_from", &request.date_from)?;
+    let date_to = parse_date("date_to", &request.date_to)?;
+
+    let mut files = Vec::new();
+    for pattern in patterns {
+        let paths =
+            glob::glob(pattern).map_err(|err| format!("Invalid log path {pattern}: {err}"))?;
+        for path in paths.flatten() {
+            if let Some(modified) = last_modified(&path) {
+                if !matches!(date_from, Some(from) if modified < from) {
+                    files.push((path, modified));
+                }
+            }
+        }
+    }
+    files.sort();
+    files.dedup();
+    if files.is_empty() {
+        return Err(format!("No log files found for type {}", request.log_type));
+    }
+
+    let bundle = std::fs::File::create(bundle_path)
+        .map_err(|err| format!("Fail to create {bundle_path}: {err}"))?;
+    let mut bundle = std::io::BufWriter::new(bundle);
+    let mut lines = 0;
+    for (file, modified) in files {
+        let selected = select_lines(&file, modified, request, date_from, date_to)?;
+
+        let write_error = |err| format!("Fail to write {bundle_path}: {err}");
+        writeln!(bundle, "==> {} <==", file.display()).map_err(write_error)?;
+        for line in selected.iter() {
+            writeln!(bundle, "{line}").map_err(write_error)?;
+        }
+        lines += selected.len();
+    }
+    bundle
+        .flush()
+        .map_err(|err| format!("Fail to write {bundle_path}: {err}"))?;
+
+    let size = std::fs::metadata(bundle_path)
+        .map_err(|err| format!("Fail to read {bundle_path}: {err}"))?
+        .len();
+    Ok(LogBundle {
+        path: bundle_path.to_string(),
+        size,
+        lines,
+    })
+}
+
+/// Read a log file line by line, keeping only the lines matching the request
+///
+/// A line with no timestamp is given the timestamp of the previous line,
+/// and the lines preceding the first timestamp of a file are given the modification date of the file.
+fn select_lines(
+    file: &Path,
+    modified: OffsetDateTime,
+    request: &LogUploadRequest,
+    date_from: Option<OffsetDateTime>,
+    date_to: Option<OffsetDateTime>,
+) -> Result<VecDeque<String>, String> {
+    let read_error = |err| format!("Fail to read {}: {err}", file.display());
+    let mut reader = BufReader::new(std::fs::File::open(file).map_err(read_error)?);
+    let mut buffer = Vec::new();
+    let mut timestamp = modified;
+    let mut selected = VecDeque::new();
+    loop {
+        buffer.clear();
+        if reader.read_until(b'\n', &mut buffer).map_err(read_error)? == 0 {
+            break;
+        }
+        let line = String::from_utf8_lossy(&buffer);
+        let line = line.trim_end_matches(['\n', '\r']);
+        if let Some(line_timestamp) = line_timestamp(line, modified) {
+            timestamp = line_timestamp;
+        }
+        if matches!(date_from, Some(from) if timestamp < from)
+            || matches!(date_to, Some(to) if timestamp > to)
+        {
+            continue;
+        }
+        if let Some(text) = &request.search_text {
+            if !line.contains(text.as_str()) {
+                continue;
+            }
+        }
+        selected.push_back(line.to_string());
+        if let Some(max_lines) = request.max_lines {
+            if selected.len() > max_lines {
+                selected.pop_front();
+            }
+        }
+    }
+    Ok(selected)
+}
+
+fn parse_date(name: &str, date: &Option<String>) -> Result<Option<OffsetDateTime>, String> {
+    date.as_ref()
+        .map(|date| {
+            OffsetDateTime::parse(date, &Rfc3339)
+                .map_err(|err| format!("Invalid {name} {date}: {err}"))
+        })
+        .transpose()
+}
+
+/// The modification date of a log file, if a regular file
+fn last_modified(path: &Path) -> Option<OffsetDateTime> {
+    let metadata = std::fs::metadata(path).ok()?;
+    if !metadata.is_file() {
+        return None;
+    }
+    Some(
+        metadata
+            .modified()
+            .map(OffsetDateTime::from)
+            .unwrap_or(OffsetDateTime::UNIX_EPOCH),
+    )
+}
+
+/// The timestamp of a log line, if the line starts with one of the supported formats:
+///
+/// - an RFC 3339 date, possibly between brackets: `2023-10-18T19:34:55Z ...` or `[2023-10-18T19:34:55+02:00] ...`,
+/// - a Unix timestamp followed by a colon, as logged by mosquitto: `1697657695: ...`,
+/// - a syslog date: `Oct 18 19:34:55 ...`.
+///
+/// A syslog date having no year nor time zone, the date is taken in UTC
+/// and in the year of the file modification, or the previous year if this date is later.
+fn line_timestamp(line: &str, modified: OffsetDateTime) -> Option<OffsetDateTime> {
+    rfc3339_timestamp(line)
+        .or_else(|| unix_timestamp(line))
+        .or_else(|| syslog_timestamp(line, modified))
+}
+
+fn rfc3339_timestamp(line: &str) -> Option<OffsetDateTime> {
+    let word = line.split_whitespace().next()?;
+    let word = word.trim_start_matches('[').trim_end_matches([']', ':']);
+    OffsetDateTime::parse(word, &Rfc3339).ok()
+}
+
+fn unix_timestamp(line: &str) -> Option<OffsetDateTime> {
+    let (seconds, _) = line.split_once(':')?;
+    if seconds.len() < 9 || !seconds.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    OffsetDateTime::from_unix_timestamp(seconds.parse().ok()?).ok()
+}
+
+fn syslog_timestamp(line: &str, modified: OffsetDateTime) -> Option<OffsetDateTime> {
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+    let date = line.get(..15)?;
+    let month = MONTHS.iter().position(|m| Some(*m) == date.get(..3))?;
+    let month = Month::try_from(month as u8 + 1).ok()?;
+    let day: u8 = date.get(3..6)?.trim().parse().ok()?;
+    let mut time = date.get(7..)?.split(':').map(|n| n.parse::<u8>().ok());
+    let time = Time::from_hms(time.next()??, time.next()??, time.next()??).ok()?;
+
+    let timestamp = |year| {
+        let date = Date::from_calendar_date(year, month, day).ok()?;
+        Some(PrimitiveDateTime::new(date, time).assume_utc())
+    };
+    match timestamp(modified.year()) {
+        Some(timestamp) if timestamp <= modified + time::Duration::DAY => Some(timestamp),
+        _ => timestamp(modified.year() - 1),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(date_from: &str, date_to: &str) -> LogUploadRequest {
+        LogUploadRequest {
+            log_type: "test".to_string(),
+            date_from: Some(date_from.to_string()),
+            date_to: Some(date_to.to_string()),
+            ..Default::default()
+        }
+    }
+
+    fn collect_file(content: &str, request: &LogUploadRequest) -> Result<String, String> {
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("test.log");
+        let bundle = dir.path().join("bundle").to_string_lossy().to_string();
+        std::fs::write(&log, content).unwrap();
+        collect(&[log.to_string_lossy().to_string()], request, &bundle)?;
+        let bundle = std::fs::read_to_string(&bundle).unwrap();
+        Ok(bundle.lines().skip(1).collect::<Vec<_>>().join("\n"))
+    }
+
+    #[test]
+    fn filter_the_lines_by_their_rfc3339_timestamp() {
+        let content = "2023-10-17T10:00:00Z before\n\
+                       2023-10-18T10:00:00Z inside\n\
+                       a continuation line\n\
+                       [2023-10-18T13:00:00+02:00] inside too\n\
+                       2023-10-19T10:00:00Z after\n\
+                       an ignored continuation line\n";
+        let request = request("2023-10-18T00:00:00Z", "2023-10-18T23:59:59Z");
+
+        assert_eq!(
+            collect_file(content, &request).unwrap(),
+            "2023-10-18T10:00:00Z inside\n\
+             a continuation line\n\
+             [2023-10-18T13:00:00+02:00] inside too"
+        );
+    }
+
+    #[test]
+    fn filter_the_lines_by_their_unix_timestamp() {
+        let content = "1697536800: before\n1697623200: inside\n1697709600: after\n";
+        let request = request("2023-10-18T00:00:00Z", "2023-10-18T23:59:59Z");
+
+        assert_eq!(
+            collect_file(content, &request).unwrap(),
+            "1697623200: inside"
+        );
+    }
+
+    #[test]
+    fn filter_the_lines_by_their_syslog_timestamp() {
+        let year = OffsetDateTime::now_utc().year();
+        let content = "Jan  1 10:00:00 host before\nJan  2 10:00:00 host inside\n";
+        let request = request(
+            &format!("{year}-01-02T00:00:00Z"),
+            &format!("{year}-01-02T23:59:59Z"),
+        );
+
+        assert_eq!(
+            collect_file(content, &request).unwrap(),
+            "Jan  2 10:00:00 host inside"
+        );
+    }
+
+    #[test]
+    fn ignore_the_non_ascii_lines_with_no_timestamp() {
+        let modified = OffsetDateTime::now_utc();
+        assert_eq!(
+            line_timestamp("naïve line with no timestamp", modified),
+            None
+        );
+        assert_eq!(line_timestamp("Jan  1 10:00:0é host", modified), None);
+        assert_eq!(line_timestamp("Jan 1é 10:00:00 host", modified), None);
+
+        let content = "naïve line with no timestamp\n";
+        let request = request("2000-01-01T00:00:00Z", "2100-01-01T00:00:00Z");
+        assert_eq!(
+            collect_file(content, &request).unwrap(),
+            "naïve line with no timestamp"
+        );
+    }
+
+    #[test]
+    fn give_the_file_modification_date_to_the_lines_with_no_timestamp() {
+        let content = "no timestamp\n";
+
+        let past = request("2000-01-01T00:00:00Z", "2000-12-31T23:59:59Z");
+        assert_eq!(collect_file(content, &past).unwrap(), "");
+
+        let now = request("2000-01-01T00:00:00Z", "2100-01-01T00:00:00Z");
+        assert_eq!(collect_file(content, &now).unwrap(), "no timestamp");
+    }
+
+    #[test]
+    fn keep_the_last_lines_containing_the_search_text() {
+        let content = "error 1\ninfo\nerror 2\nerror 3\n";
+        let request = LogUploadRequest {
+            log_type: "test".to_string(),
+            search_text: Some("error".to_string()),
+            max_lines: Some(2),
+            ..Default::default()
+        };
+
+        assert_eq!(collect_file(content, &request).unwrap(), "error 2\nerror 3");
+    }
+}
@@ -0,0 +1,41 @@
+use crate::operations_sm::plugin::PluginConfig;
+use serde::{Deserialize, Serialize};
+
+/// The configuration of the log plugin
+///
+/// Lists the log files that can be uploaded, grouped by type.
+///
+/// ```toml
+/// [[files]]
+/// type = "mosquitto"
+/// path = "/var/log/mosquitto/*.log"
+/// ```
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct LogPluginConfig {
+    #[serde(default)]
+    pub files: Vec<LogTarget>,
+}
+
+/// A set of log files managed by the log plugin
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct LogTarget {
+    /// The log type, as used by the requests to designate these files
+    #[serde(rename = "type")]
+    pub log_type: String,
+
+    /// A glob pattern matching the log files
+    pub path: String,
+}
+
+impl LogPluginConfig {
+    /// The glob patterns of all the log files of the given type
+    pub fn get_patterns(&self, log_type: &str) -> Vec<String> {
+        self.files
+            .iter()
+            .filter(|target| target.log_type == log_type)
+            .map(|target| target.path.clone())
+            .collect()
+    }
+}
+
+impl PluginConfig for LogPluginConfig {}
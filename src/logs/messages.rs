@@ -0,0 +1,124 @@
+use serde::Deserialize;
+use serde::Serialize;
+use tedge_mqtt_state_machine_macros::OperationState;
+
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct LogUploadRequest {
+    /// The type of the log files to upload
+    #[serde(rename = "type")]
+    pub log_type: String,
+
+    /// The url where the log files have to be uploaded
+    pub dst_url: String,
+
+    /// Only the lines logged after this RFC 3339 date are collected
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub date_from: Option<String>,
+
+    /// Only the lines logged before this RFC 3339 date are collected
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub date_to: Option<String>,
+
+    /// Only the lines containing this text are collected
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub search_text: Option<String>,
+
+    /// Only the last lines of each file are collected
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_lines: Option<usize>,
+}
+
+/// The log files collected into a single file
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct LogBundle {
+    /// The path of the bundle
+    pub path: String,
+
+    /// The size of the bundle, in bytes
+    pub size: u64,
+
+    /// The number of log lines in the bundle
+    pub lines: usize,
+}
+
+/// The states of a log upload request
+///
+/// The default workflow of the operation is derived from the `next` attributes of the states.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize, OperationState)]
+#[serde(tag = "status", rename_all = "kebab-case")]
+#[operation(operation = "log", request = "upload")]
+pub enum LogUploadState {
+    /// The default behavior is to immediately collect the requested log files.
+    #[operation(next = ["collecting", "failed", "cancelled"])]
+    Init {
+        #[serde(flatten)]
+        request: LogUploadRequest,
+    },
+    #[operation(next = ["collected", "failed", "cancelled"])]
+    Collecting {
+        #[serde(flatten)]
+        request: LogUploadRequest,
+    },
+    /// The default behavior is to immediately upload the collected log files.
+    /// A user-defined script can be attached to this state to redact the bundle before upload.
+    #[operation(next = ["uploading", "failed", "cancelled"])]
+    Collected {
+        #[serde(flatten)]
+        request: LogUploadRequest,
+        #[serde(flatten)]
+        bundle: LogBundle,
+    },
+    #[operation(next = ["successful", "failed", "cancelled"])]
+    Uploading {
+        #[serde(flatten)]
+        request: LogUploadRequest,
+        #[serde(flatten)]
+        bundle: LogBundle,
+    },
+    #[operation(next = [])]
+    Successful {
+        #[serde(flatten)]
+        request: LogUploadRequest,
+        #[serde(flatten)]
+        bundle: LogBundle,
+    },
+    #[operation(next = [])]
+    Failed {
+        #[serde(flatten)]
+        request: LogUploadRequest,
+        #[serde(default)]
+        reason: String,
+    },
+    #[operation(next = [])]
+    Cancelled {
+        #[serde(flatten)]
+        request: LogUploadRequest,
+        #[serde(default)]
+        reason: String,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn the_bundle_is_flattened_along_the_request() {
+        let payload = json!({
+            "status": "collected",
+            "type": "mosquitto",
+            "dst_url": "http://localhost:8000/mosquitto.log",
+            "max_lines": 100,
+            "path": "/tmp/log.bundle",
+            "size": 1024,
+            "lines": 42,
+        });
+
+        let state: LogUploadState = serde_json::from_value(payload.clone()).unwrap();
+        assert_eq!(state.status(), "collected");
+        assert_eq!(state.request().max_lines, Some(100));
+        assert_eq!(state.bundle().map(|b| b.lines), Some(42));
+        assert_eq!(serde_json::to_value(&state).unwrap(), payload);
+    }
+}
@@ -0,0 +1,4 @@
+pub mod actor;
+pub mod collect;
+pub mod config;
+pub mod messages;
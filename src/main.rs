@@ -1,25 +1,50 @@
+pub mod command;
 pub mod configuration;
+pub mod device;
+pub mod firmware;
+pub mod logs;
 pub mod operations_sm;
+pub mod software;
 
-use crate::configuration::builder::ConfigManagerBuilder;
+use crate::command::actor::CommandManager;
+use crate::command::config::CommandPluginConfig;
+use crate::configuration::actor::ConfigManager;
+use crate::configuration::config::ConfigPluginConfig;
+use crate::configuration::list::actor::ConfigListManager;
+use crate::configuration::snapshot::actor::ConfigSnapshotManager;
+use crate::device::actor::DeviceManager;
+use crate::device::config::DevicePluginConfig;
+use crate::firmware::actor::FirmwareManager;
+use crate::firmware::config::FirmwarePluginConfig;
+use crate::logs::actor::LogManager;
+use crate::logs::config::LogPluginConfig;
 use crate::operations_sm::builder::OperationsActorBuilder;
-use tedge_actors::ServerActorBuilder;
-use tedge_actors::{Concurrent, Runtime};
+use crate::operations_sm::journal::Journal;
+use crate::operations_sm::plugin::{OperationPluginBuilder, PluginConfig};
+use crate::software::actor::SoftwareManager;
+use crate::software::config::SoftwarePluginConfig;
+use crate::software::list::actor::SoftwareListManager;
+use tedge_actors::Runtime;
 use tedge_mqtt_ext::{MqttActorBuilder, MqttConfig};
-use tedge_script_ext::ScriptActor;
 use tedge_signal_ext::SignalActor;
 
 #[tokio::main]
 async fn main() -> Result<(), anyhow::Error> {
     env_logger::init();
 
+    let args: Vec<String> = std::env::args().collect();
+    if let [_, command, topic] = args.as_slice() {
+        if command == "history" {
+            return print_history(topic);
+        }
+    }
+
     let mqtt_config = MqttConfig::default().with_session_name("Experimental MQTT State Machine");
 
     let mut runtime = Runtime::try_new(None).await?;
     let signal_actor = SignalActor::builder(&runtime.get_handle());
     let mut mqtt_actor = MqttActorBuilder::new(mqtt_config);
-    let mut script_runner: ServerActorBuilder<ScriptActor, Concurrent> = ScriptActor::builder();
-    let mut operations_actor = OperationsActorBuilder::new(&mut mqtt_actor, &mut script_runner);
+    let mut operations_actor = OperationsActorBuilder::new(&mut mqtt_actor, Journal::default());
 
     for entry in std::fs::read_dir("./operations")? {
         if let Ok(entry) = entry {
@@ -30,13 +55,78 @@ async fn main() -> Result<(), anyhow::Error> {
         }
     }
 
-    let config_manager = ConfigManagerBuilder::new(&mut operations_actor);
+    let config_plugin_config = ConfigPluginConfig::from_file("./plugins/configuration.toml")?;
+    let config_manager = OperationPluginBuilder::new(
+        ConfigManager::new(config_plugin_config.clone()),
+        &mut operations_actor,
+    );
+    let config_snapshot_manager = OperationPluginBuilder::new(
+        ConfigSnapshotManager::new(config_plugin_config.clone()),
+        &mut operations_actor,
+    );
+    let config_list_manager = OperationPluginBuilder::new(
+        ConfigListManager::new(config_plugin_config),
+        &mut operations_actor,
+    );
+
+    let software_plugin_config = SoftwarePluginConfig::from_file("./plugins/software.toml")?;
+    let software_manager = OperationPluginBuilder::new(
+        SoftwareManager::new(software_plugin_config.clone()),
+        &mut operations_actor,
+    );
+    let software_list_manager = OperationPluginBuilder::new(
+        SoftwareListManager::new(software_plugin_config),
+        &mut operations_actor,
+    );
+
+    let firmware_plugin_config = FirmwarePluginConfig::from_file("./plugins/firmware.toml")?;
+    let firmware_manager = OperationPluginBuilder::new(
+        FirmwareManager::new(firmware_plugin_config),
+        &mut operations_actor,
+    );
+
+    let device_plugin_config = DevicePluginConfig::from_file("./plugins/device.toml")?;
+    let device_manager = OperationPluginBuilder::new(
+        DeviceManager::new(device_plugin_config),
+        &mut operations_actor,
+    );
+
+    let log_plugin_config = LogPluginConfig::from_file("./plugins/log.toml")?;
+    let log_manager =
+        OperationPluginBuilder::new(LogManager::new(log_plugin_config), &mut operations_actor);
+
+    let command_plugin_config = CommandPluginConfig::from_file("./plugins/command.toml")?;
+    let command_manager = OperationPluginBuilder::new(
+        CommandManager::new(command_plugin_config),
+        &mut operations_actor,
+    );
 
     runtime.spawn(signal_actor).await?;
     runtime.spawn(mqtt_actor).await?;
-    runtime.spawn(script_runner).await?;
     runtime.spawn(operations_actor).await?;
     runtime.spawn(config_manager).await?;
+    runtime.spawn(config_snapshot_manager).await?;
+    runtime.spawn(config_list_manager).await?;
+    runtime.spawn(software_manager).await?;
+    runtime.spawn(software_list_manager).await?;
+    runtime.spawn(firmware_manager).await?;
+    runtime.spawn(device_manager).await?;
+    runtime.spawn(log_manager).await?;
+    runtime.spawn(command_manager).await?;
     runtime.run_to_completion().await?;
     Ok(())
 }
+
+/// Print the timeline of an operation, as recorded in the journal
+fn print_history(topic: &str) -> Result<(), anyhow::Error> {
+    for entry in Journal::default().history(topic)? {
+        println!(
+            "{} {:<11} {:<16} {}",
+            entry.timestamp,
+            format!("{:?}", entry.source).to_lowercase(),
+            entry.status,
+            entry.digest
+        );
+    }
+    Ok(())
+}
@@ -1,27 +1,57 @@
-use crate::operations_sm::config::OperationWorkflow;
+use crate::operations_sm::config::{OperationState, OperationWorkflow};
+use crate::operations_sm::journal::{Journal, TransitionSource};
+use crate::operations_sm::owners::{ExternalOwners, OwnerMessage, OwnerRegistration};
+use crate::operations_sm::script::execute;
 use async_trait::async_trait;
-use log::{error, info};
+use log::{debug, error, info, warn};
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::process::Output;
+use std::time::Duration;
+use tedge_actors::futures::future::{AbortHandle, Abortable, Aborted};
+use tedge_actors::futures::stream::FuturesUnordered;
+use tedge_actors::futures::StreamExt;
 use tedge_actors::{
-    Actor, ChannelError, ClientMessageBox, DynSender, LoggingReceiver, MessageReceiver,
-    RuntimeError, Sender,
+    Actor, ChannelError, DynSender, LoggingReceiver, MessageReceiver, RuntimeError, Sender,
 };
 use tedge_mqtt_ext::{MqttMessage, Topic, TopicFilter};
 use tedge_script_ext::Execute;
+use tokio::task::JoinHandle;
 
-use crate::operations_sm::messages::{OperationInput, OperationPluginMessage};
+use crate::operations_sm::messages::{
+    ChildDelegation, OperationCancelRequest, OperationInput, OperationPluginError,
+    OperationPluginEvent, OperationPluginMessage,
+};
 
 pub struct OperationsActor {
     input_receiver: LoggingReceiver<OperationInput>,
     mqtt_sender: DynSender<MqttMessage>,
-    script_runner: ClientMessageBox<Execute, std::io::Result<Output>>,
+
+    /// The journal where are recorded all the transitions
+    journal: Journal,
+
+    /// The on-going operation instances, indexed by topic
+    operations: HashMap<String, OperationInstance>,
+
+    /// The scripts launched to move forward the operations
+    scripts: FuturesUnordered<JoinHandle<Result<ScriptOutcome, Aborted>>>,
+
+    /// The timers armed for the states delegated to child devices,
+    /// each returning the topic and the step of the delegated state
+    child_timers: FuturesUnordered<JoinHandle<(String, u64)>>,
+
+    /// The out-of-process plugins registered as owners of operation states
+    owners: ExternalOwners,
+
+    /// The timers armed on each heartbeat of an external owner,
+    /// each returning the name of the owner and its number of heartbeats
+    owner_timers: FuturesUnordered<JoinHandle<(String, u64)>>,
 
     /// All the operation workflow definitions,
     /// possibly with a channel to the actor operation plugin that implement the workflow
     workflows: Vec<(
         TopicFilter,
         OperationWorkflow,
-        Option<DynSender<OperationPluginMessage>>,
+        Option<DynSender<OperationPluginEvent>>,
     )>,
 }
 
@@ -32,17 +62,48 @@ impl Actor for OperationsActor {
     }
 
     async fn run(&mut self) -> Result<(), RuntimeError> {
-        while let Some(input) = self.input_receiver.recv().await {
-            match input {
-                OperationInput::MqttMessage(event) => {
-                    self.handle_mqtt_operation_event(event).await?
+        self.publish_capabilities().await?;
+        loop {
+            tokio::select! {
+                Some(input) = self.input_receiver.recv() => {
+                    match input {
+                        OperationInput::MqttMessage(event) => {
+                            self.handle_mqtt_operation_event(event).await?
+                        }
+                        OperationInput::OperationPluginMessage(event) => {
+                            self.handle_operation_plugin_event(event).await?
+                        }
+                        OperationInput::OperationPluginError(error) => {
+                            self.handle_operation_plugin_error(error).await?
+                        }
+                    }
+                }
+                Some(outcome) = self.scripts.next() => {
+                    match outcome {
+                        Ok(Ok(outcome)) => self.end_script(outcome).await?,
+                        Ok(Err(Aborted)) => {
+                            // The operation has been cancelled while the script was running
+                        }
+                        Err(err) => error!("Fail to run a workflow script: {err}"),
+                    }
                 }
-                OperationInput::OperationPluginMessage(event) => {
-                    self.publish_operation_plugin_event(event).await?
+                Some(timer) = self.child_timers.next() => {
+                    match timer {
+                        Ok((topic, step)) => self.handle_child_timeout(topic, step).await?,
+                        Err(err) => error!("Fail to run a child device timer: {err}"),
+                    }
+                }
+                Some(timer) = self.owner_timers.next() => {
+                    match timer {
+                        Ok((name, beats)) => self.handle_owner_timeout(name, beats).await?,
+                        Err(err) => error!("Fail to run an owner heartbeat timer: {err}"),
+                    }
+                }
+                else => {
+                    return Ok(());
                 }
             }
         }
-        Ok(())
     }
 }
 
@@ -52,23 +113,33 @@ impl OperationsActor {
     }
 
     pub fn subscriptions() -> TopicFilter {
-        TopicFilter::new_unchecked("tedge/operations/+/+/+/+")
+        let mut topics = TopicFilter::new_unchecked("tedge/operations/+/+/+/+");
+        topics.add_unchecked("tedge/operations/+/+/+/+/cancel");
+        topics.add_unchecked("tedge/operations/+/+/+/+/child/response");
+        topics.add_unchecked("tedge/operations/owners/+");
+        topics.add_unchecked("tedge/operations/owners/+/heartbeat");
+        topics
     }
 
     pub fn new(
         input_receiver: LoggingReceiver<OperationInput>,
         mqtt_sender: DynSender<MqttMessage>,
-        script_runner: ClientMessageBox<Execute, std::io::Result<Output>>,
+        journal: Journal,
         workflows: Vec<(
             TopicFilter,
             OperationWorkflow,
-            Option<DynSender<OperationPluginMessage>>,
+            Option<DynSender<OperationPluginEvent>>,
         )>,
     ) -> Self {
         OperationsActor {
             input_receiver,
             mqtt_sender,
-            script_runner,
+            journal,
+            operations: HashMap::new(),
+            scripts: FuturesUnordered::new(),
+            child_timers: FuturesUnordered::new(),
+            owners: ExternalOwners::default(),
+            owner_timers: FuturesUnordered::new(),
             workflows,
         }
     }
@@ -77,20 +148,589 @@ impl OperationsActor {
         &mut self,
         event: MqttMessage,
     ) -> Result<(), ChannelError> {
+        if event
+            .topic
+            .name
+            .ends_with(OperationCancelRequest::TOPIC_SUFFIX)
+        {
+            return match OperationCancelRequest::try_from(&event) {
+                Ok(request) => self.handle_cancel_request(request).await,
+                Err(err) => {
+                    error!("Ignore message on {}: {err}", event.topic.name);
+                    Ok(())
+                }
+            };
+        }
+
+        if event
+            .topic
+            .name
+            .starts_with(OwnerRegistration::TOPIC_PREFIX)
+        {
+            return match OwnerMessage::try_from(&event) {
+                Ok(message) => self.handle_owner_message(message).await,
+                Err(err) => {
+                    error!("Ignore message on {}: {err}", event.topic.name);
+                    Ok(())
+                }
+            };
+        }
+
+        if event.topic.name.ends_with(ChildDelegation::RESPONSE_SUFFIX) {
+            return match ChildDelegation::response(&event) {
+                Ok(response) => self.handle_child_response(response).await,
+                Err(err) => {
+                    error!("Ignore message on {}: {err}", event.topic.name);
+                    Ok(())
+                }
+            };
+        }
+
+        if event.payload_bytes().is_empty() {
+            info!("Operation {} cleared", event.topic.name);
+            self.operations.remove(&event.topic.name);
+            return Ok(());
+        }
+
         match OperationPluginMessage::try_from(&event) {
             Err(err) => {
                 error!("Ignore message on {}: {err}", event.topic.name);
                 Ok(())
             }
-            Ok(operation_state) => self.operation_update(event.topic, operation_state).await,
+            Ok(operation_state) => {
+                // A retained message is received only when subscribing,
+                // i.e. for operations that were on-going when the daemon was restarted.
+                let recovered = event.retain;
+                self.accept_operation_state(event.topic, operation_state, recovered)
+                    .await
+            }
+        }
+    }
+
+    /// Process a new state received over MQTT for an operation.
+    ///
+    /// The states of an operation instance are processed one after the other:
+    /// while a script is running for an operation, the new states are queued.
+    async fn accept_operation_state(
+        &mut self,
+        topic: Topic,
+        operation_state: OperationPluginMessage,
+        recovered: bool,
+    ) -> Result<(), ChannelError> {
+        let digest = operation_state.digest();
+        let previous_status = self
+            .operations
+            .get(&topic.name)
+            .filter(|instance| !instance.digest.is_empty() && instance.digest != digest)
+            .map(|instance| instance.status.clone());
+        if let Some(previous_status) = previous_status {
+            let was_terminal = self
+                .get_state(&topic.name, &previous_status)
+                .is_some_and(|state| state.next.is_empty());
+            if was_terminal {
+                // A terminal state is owned by no one: any participant can publish a new request on the topic,
+                // but not on behalf of tedge, e.g. a late copy of a state published before the terminal one.
+                if let Some(publisher) = operation_state
+                    .publisher()
+                    .filter(|publisher| OwnerRegistration::RESERVED_NAMES.contains(publisher))
+                {
+                    warn!(
+                        "Reject the {} state of {}: {publisher} is a reserved name, while {previous_status} is terminal",
+                        operation_state.status, topic.name
+                    );
+                    return Ok(());
+                }
+                if self
+                    .operations
+                    .get(&topic.name)
+                    .is_some_and(|instance| instance.running_script.is_none())
+                {
+                    self.operations.remove(&topic.name);
+                }
+            } else if !recovered
+                && !self.check_publisher(&topic.name, &previous_status, &operation_state, &digest)
+            {
+                return Ok(());
+            }
+        }
+
+        let is_terminal = self
+            .get_state(&topic.name, &operation_state.status)
+            .is_some_and(|state| state.next.is_empty());
+        let instance = self.operations.entry(topic.name.clone()).or_default();
+
+        if instance.digest == digest
+            || instance
+                .pending
+                .iter()
+                .any(|(_, s, _)| s.digest() == digest)
+        {
+            debug!(
+                "Ignore operation event {}: {} already processed",
+                topic.name, operation_state.status
+            );
+            return Ok(());
+        }
+
+        // Only the states published by tedge carry a reliable step:
+        // the other participants are not expected to bump the step they copied from the previous state.
+        let step = match operation_state.step() {
+            _ if instance.digest.is_empty() => operation_state.step().unwrap_or(0),
+            Some(step) if instance.published.contains(&digest) => {
+                if step <= instance.step {
+                    warn!(
+                        "Ignore out-of-order operation event {}: {} at step {step} while the operation is {} at step {}",
+                        topic.name, operation_state.status, instance.status, instance.step
+                    );
+                    return Ok(());
+                }
+                step
+            }
+            _ => instance.step + 1,
+        };
+
+        if instance.running_script.is_some() {
+            debug!(
+                "Delay operation event {}: {} received while a script is running",
+                topic.name, operation_state.status
+            );
+            instance
+                .pending
+                .push_back((topic, operation_state, recovered));
+            return Ok(());
+        }
+
+        let superseded = instance.published.contains(&digest)
+            && instance
+                .last_published
+                .as_ref()
+                .is_some_and(|(_, published_step)| step < *published_step);
+
+        instance.status = operation_state.status.clone();
+        instance.step = step;
+        instance.digest = digest;
+        instance.state = Some(operation_state.clone());
+
+        if superseded {
+            debug!(
+                "Skip operation event {}: {} already superseded by a new state",
+                topic.name, operation_state.status
+            );
+            return Ok(());
+        }
+
+        // A terminal state is only followed by a new request, which starts afresh
+        if is_terminal {
+            instance
+                .published
+                .retain(|published| published == &instance.digest);
+        }
+
+        // The states published by tedge have been journaled along their actual source
+        if !instance.published.contains(&instance.digest) {
+            self.journal
+                .record(TransitionSource::Incoming, &operation_state);
+        }
+
+        self.operation_update(topic, operation_state, recovered)
+            .await
+    }
+
+    /// Check that a new state is published by the owner of the previous state,
+    /// logging the rejected and the flagged states (see [check_publisher]).
+    fn check_publisher(
+        &self,
+        topic: &str,
+        previous_status: &str,
+        operation_state: &OperationPluginMessage,
+        digest: &str,
+    ) -> bool {
+        let Some(owner) = self
+            .get_state(topic, previous_status)
+            .map(|state| state.owner.as_str())
+        else {
+            return true;
+        };
+        let published_by_tedge = self
+            .operations
+            .get(topic)
+            .is_some_and(|instance| instance.published.contains(digest));
+        let operation = Topic::new_unchecked(topic);
+        let registered: Vec<&str> = self
+            .owners
+            .owners_of(&operation, previous_status, owner)
+            .map(|(name, _)| name.as_str())
+            .collect();
+
+        let status = &operation_state.status;
+        match check_publisher(
+            owner,
+            operation_state.publisher(),
+            published_by_tedge,
+            &registered,
+        ) {
+            PublisherCheck::Accepted => true,
+            PublisherCheck::Flagged(reason) => {
+                warn!("Accept the {status} state of {topic}, but {reason}, while {previous_status} is owned by {owner}");
+                true
+            }
+            PublisherCheck::Rejected(reason) => {
+                warn!("Reject the {status} state of {topic}: {reason}, while {previous_status} is owned by {owner}");
+                false
+            }
         }
     }
 
-    /// Publish over MQTT the new state for an operation
+    /// Cancel an operation, if the workflow declares a transition to `cancelled` from its current state.
+    ///
+    /// Any script running for this operation is killed,
+    /// and the plugin that owns the current state is notified so it can abort any work in progress.
+    async fn handle_cancel_request(
+        &mut self,
+        request: OperationCancelRequest,
+    ) -> Result<(), ChannelError> {
+        let topic: String = (&request.operation).into();
+        let Some(current_state) = self
+            .operations
+            .get(&topic)
+            .and_then(|instance| instance.state.clone())
+        else {
+            warn!("Ignore cancel request for {topic}: unknown operation");
+            return Ok(());
+        };
+
+        let status = current_state.status.clone();
+        let can_be_cancelled = self
+            .get_state(&topic, &status)
+            .map(|state| state.next.iter().any(|next| next == CANCELLED))
+            .unwrap_or(false);
+        if !can_be_cancelled {
+            warn!("Reject cancel request for {topic}: {status} can not be cancelled");
+            return Ok(());
+        }
+
+        let mut step = 0;
+        if let Some(instance) = self.operations.get_mut(&topic) {
+            if let Some(script) = instance.running_script.take() {
+                info!("Kill the script running for {topic}");
+                script.abort();
+            }
+            instance.pending.clear();
+            step = instance.latest().1 + 1;
+        }
+
+        if let OperationAction::Internal(mut sender) =
+            self.get_workflow_state(&Topic::new_unchecked(&topic), &status)
+        {
+            info!("Cancel operation {topic}: builtin step");
+            sender
+                .send(OperationPluginEvent::Cancel(current_state.clone()))
+                .await?
+        }
+
+        let new_state = current_state.cancelled_with(request.reason).with_step(step);
+        self.journal.record(TransitionSource::Cancel, &new_state);
+        self.publish_operation_plugin_event(new_state).await
+    }
+
+    /// Publish the new state computed by an operation plugin,
+    /// unless the operation moved meanwhile to a state from which this new state is not a transition.
+    ///
+    /// The new state is checked against the last state published by tedge, even if not received back yet:
+    /// a plugin might return an intermediate state and then the outcome of a background task,
+    /// before the echo of the intermediate state.
+    async fn handle_operation_plugin_event(
+        &mut self,
+        event: OperationPluginMessage,
+    ) -> Result<(), ChannelError> {
+        let topic: String = (&event.operation).into();
+        let Some(instance) = self.operations.get(&topic) else {
+            warn!(
+                "Discard the {} state of {topic}: unknown operation",
+                event.status
+            );
+            return Ok(());
+        };
+        let (current_status, current_step) = instance.latest();
+        let is_transition = self
+            .get_state(&topic, current_status)
+            .map(|state| state.next.contains(&event.status))
+            .unwrap_or(false);
+        if !is_transition {
+            warn!(
+                "Discard the {} state of {topic}: the operation is now {current_status}",
+                event.status
+            );
+            return Ok(());
+        }
+
+        let new_state = event.with_step(current_step + 1);
+        self.journal.record(TransitionSource::Plugin, &new_state);
+        self.publish_operation_plugin_event(new_state).await
+    }
+
+    /// Fail an operation on a plugin error,
+    /// unless the operation moved meanwhile to another state.
+    async fn handle_operation_plugin_error(
+        &mut self,
+        error: OperationPluginError,
+    ) -> Result<(), ChannelError> {
+        let topic: String = (&error.operation).into();
+        let reason = error.reason;
+        let current_status = self
+            .operations
+            .get(&topic)
+            .map(|instance| instance.latest().0.to_string());
+        if let Some(current_status) = current_status {
+            if current_status != error.status {
+                warn!("Ignore a plugin error on {topic}: the operation is now {current_status}: {reason}");
+                return Ok(());
+            }
+        }
+
+        error!("Plugin error on {topic}: {reason}");
+        self.fail_operation(&topic, reason, TransitionSource::PluginError)
+            .await
+    }
+
+    /// Fail an operation, unless unknown or already terminated.
+    ///
+    /// The operation is failed even if the workflow doesn't declare a transition to `failed`,
+    /// as the operation would be stuck otherwise.
+    async fn fail_operation(
+        &mut self,
+        topic: &str,
+        reason: String,
+        source: TransitionSource,
+    ) -> Result<(), ChannelError> {
+        let Some(instance) = self.operations.get(topic) else {
+            return Ok(());
+        };
+        let step = instance.latest().1;
+        let Some(current_state) = instance.state.clone() else {
+            return Ok(());
+        };
+        let current_status = &current_state.status;
+        let is_terminal = self
+            .get_state(topic, current_status)
+            .map(|state| state.next.is_empty())
+            .unwrap_or(true);
+        if is_terminal {
+            warn!("Can not fail {topic}, in the terminal state {current_status}: {reason}");
+            return Ok(());
+        }
+
+        let new_state = current_state.failed_with(reason).with_step(step + 1);
+        self.journal.record(source, &new_state);
+        self.publish_operation_plugin_event(new_state).await
+    }
+
+    /// Track the registrations and the heartbeats of the external owners
+    async fn handle_owner_message(&mut self, message: OwnerMessage) -> Result<(), ChannelError> {
+        match message {
+            OwnerMessage::Register(name, registration) => {
+                let heartbeat = registration.heartbeat;
+                let workflows = self.workflows.iter().map(|(_, workflow, _)| workflow);
+                match self.owners.register(&name, registration, workflows) {
+                    Ok(beats) => {
+                        info!("Register the external owner {name}");
+                        self.start_owner_timer(name, beats, heartbeat);
+                    }
+                    Err(err) => {
+                        error!("Reject the registration of {name}: {err}");
+                        if self.owners.is_registered(&name) {
+                            self.owners.expire_now(&name);
+                            self.fail_orphaned_operations(&name).await?;
+                            self.owners.remove(&name);
+                        }
+                    }
+                }
+                self.publish_capabilities().await?;
+            }
+            OwnerMessage::Unregister(name) => {
+                info!("Unregister the external owner {name}");
+                self.owners.expire_now(&name);
+                self.fail_orphaned_operations(&name).await?;
+                self.owners.unregister(&name);
+                self.publish_capabilities().await?;
+            }
+            OwnerMessage::Heartbeat(name) => {
+                let was_alive = self.owners.is_alive(&name);
+                match self.owners.heartbeat(&name) {
+                    Some((beats, heartbeat)) => {
+                        self.start_owner_timer(name, beats, heartbeat);
+                        if !was_alive {
+                            self.publish_capabilities().await?;
+                        }
+                    }
+                    None => warn!("Ignore the heartbeat of {name}: unknown owner"),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Declare an external owner lost when no heartbeat has been received in time,
+    /// failing the operations that are waiting for this owner.
+    async fn handle_owner_timeout(&mut self, name: String, beats: u64) -> Result<(), ChannelError> {
+        if self.owners.expire(&name, beats) {
+            warn!("The external owner {name} is no longer alive");
+            self.fail_orphaned_operations(&name).await?;
+            self.publish_capabilities().await?;
+        }
+        Ok(())
+    }
+
+    /// Fail the operations in a state owned by a lost external owner,
+    /// unless another registered owner of this state is still alive.
+    async fn fail_orphaned_operations(&mut self, lost: &str) -> Result<(), ChannelError> {
+        let mut orphans = vec![];
+        for (topic, instance) in self.operations.iter() {
+            let topic = Topic::new_unchecked(topic);
+            let status = &instance.status;
+            if let OperationAction::External(owner) = self.get_workflow_state(&topic, status) {
+                let owners: Vec<_> = self.owners.owners_of(&topic, status, &owner).collect();
+                if owners.iter().any(|(name, _)| name.as_str() == lost)
+                    && owners.iter().all(|(_, owner)| !owner.alive)
+                {
+                    orphans.push(topic.name);
+                }
+            }
+        }
+
+        for topic in orphans {
+            let reason = format!("The external owner {lost} is no longer alive");
+            self.fail_operation(&topic, reason, TransitionSource::OwnerLost)
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Arm a timer to check that an external owner sends its next heartbeat in time.
+    ///
+    /// A grace period of one heartbeat interval is given to the owner.
+    fn start_owner_timer(&mut self, name: String, beats: u64, heartbeat: u64) {
+        self.owner_timers.push(tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_secs(2 * heartbeat)).await;
+            (name, beats)
+        }));
+    }
+
+    /// Publish the capability report, listing the workflows and the external owners
+    async fn publish_capabilities(&mut self) -> Result<(), ChannelError> {
+        let workflows = self.workflows.iter().map(|(_, workflow, _)| workflow);
+        let report = self.owners.capability_report(workflows);
+        self.mqtt_sender.send(report).await
+    }
+
+    /// Mirror on the operation topic the new state published by a child device,
+    /// provided the current state is delegated to the child and the new state is a transition from it.
+    async fn handle_child_response(
+        &mut self,
+        response: OperationPluginMessage,
+    ) -> Result<(), ChannelError> {
+        let topic: String = (&response.operation).into();
+        let Some(instance) = self.operations.get(&topic) else {
+            warn!(
+                "Reject the {} state of {topic} sent by the child device: unknown operation",
+                response.status
+            );
+            return Ok(());
+        };
+        let current_status = instance.status.clone();
+        let step = instance.latest().1;
+        let Some(current_state) = instance.state.clone() else {
+            return Ok(());
+        };
+
+        let child = &response.operation.subsystem;
+        match check_publisher(child, response.publisher(), false, &[]) {
+            PublisherCheck::Accepted => {}
+            PublisherCheck::Flagged(reason) => {
+                warn!(
+                    "Accept the {} state of {topic} sent by the child device, but {reason}",
+                    response.status
+                );
+            }
+            PublisherCheck::Rejected(reason) => {
+                warn!(
+                    "Reject the {} state of {topic} sent by the child device: {reason}",
+                    response.status
+                );
+                return Ok(());
+            }
+        }
+
+        if !matches!(
+            self.get_workflow_state(&Topic::new_unchecked(&topic), &current_status),
+            OperationAction::Child(_)
+        ) {
+            warn!(
+                "Reject the {} state of {topic} sent by the child device: {current_status} is not delegated to the child",
+                response.status
+            );
+            return Ok(());
+        }
+        let is_transition = self
+            .get_state(&topic, &current_status)
+            .map(|state| state.next.contains(&response.status))
+            .unwrap_or(false);
+        if !is_transition {
+            warn!(
+                "Reject the {} state of {topic} sent by the child device: not a transition from {current_status}",
+                response.status
+            );
+            return Ok(());
+        }
+
+        let new_state = current_state.merge(response).with_step(step + 1);
+        self.journal.record(TransitionSource::Child, &new_state);
+        self.publish_operation_plugin_event(new_state).await
+    }
+
+    /// Fail an operation still waiting for a child device when the delegated state times out
+    async fn handle_child_timeout(&mut self, topic: String, step: u64) -> Result<(), ChannelError> {
+        let Some(instance) = self.operations.get(&topic) else {
+            return Ok(());
+        };
+        if instance.latest().1 != step {
+            // The child device responded in time
+            return Ok(());
+        }
+        let Some(current_state) = instance.state.clone() else {
+            return Ok(());
+        };
+
+        let status = &current_state.status;
+        let can_fail = self
+            .get_state(&topic, status)
+            .map(|state| state.next.iter().any(|next| next == FAILED))
+            .unwrap_or(false);
+        let subsystem = &current_state.operation.subsystem;
+        if !can_fail {
+            warn!("The child device {subsystem} did not respond in time for {topic}: {status} can not be failed");
+            return Ok(());
+        }
+
+        let reason = format!("The child device {subsystem} did not respond in time");
+        let new_state = current_state.failed_with(reason).with_step(step + 1);
+        self.journal.record(TransitionSource::Timeout, &new_state);
+        self.publish_operation_plugin_event(new_state).await
+    }
+
+    /// Publish over MQTT the new state for an operation,
+    /// recording its digest to recognize its echo.
     async fn publish_operation_plugin_event(
         &mut self,
         event: OperationPluginMessage,
     ) -> Result<(), ChannelError> {
+        let event = event.with_publisher(TEDGE_OWNER);
+        let topic: String = (&event.operation).into();
+        if let Some(instance) = self.operations.get_mut(&topic) {
+            instance.published.insert(event.digest());
+            let step = event.step().unwrap_or_default();
+            instance.last_published = Some((event.status.clone(), step));
+        }
         match event.try_into() {
             Ok(mqtt_message) => {
                 let mqtt_message: MqttMessage = mqtt_message;
@@ -107,27 +747,64 @@ impl OperationsActor {
         &mut self,
         topic: Topic,
         operation_state: OperationPluginMessage,
+        recovered: bool,
     ) -> Result<(), ChannelError> {
+        let is_terminal = self
+            .get_state(&topic.name, &operation_state.status)
+            .is_some_and(|state| state.next.is_empty());
+        if is_terminal && self.is_delegated_to_child(&topic) {
+            self.mqtt_sender
+                .send(ChildDelegation::clear(&operation_state.operation))
+                .await?;
+        }
+
         match self.get_workflow_state(&topic, &operation_state.status) {
             OperationAction::Unknown => {
                 error!("Ignore operation event {}: unknown", topic.name);
             }
             OperationAction::External(external) => {
+                let status = &operation_state.status;
+                let owners: Vec<_> = self.owners.owners_of(&topic, status, &external).collect();
+                if !owners.is_empty() && owners.iter().all(|(_, owner)| !owner.alive) {
+                    let reason = format!("The external owner {external} is no longer alive");
+                    return self
+                        .fail_operation(&topic.name, reason, TransitionSource::OwnerLost)
+                        .await;
+                }
                 info!(
                     "Ignore operation event {}: delegated to {external}",
                     topic.name
                 );
             }
+            OperationAction::Child(timeout) => {
+                info!(
+                    "Process operation event {}: delegated to the child",
+                    topic.name
+                );
+                self.mqtt_sender
+                    .send(ChildDelegation::request(&operation_state))
+                    .await?;
+                self.start_child_timer(topic.name, timeout);
+            }
             OperationAction::Internal(mut sender) => {
-                info!("Process operation event {}: builtin step", topic.name);
-                sender.send(operation_state).await?
+                if recovered {
+                    info!("Recover operation event {}: builtin step", topic.name);
+                    sender
+                        .send(OperationPluginEvent::Recover(operation_state))
+                        .await?
+                } else {
+                    info!("Process operation event {}: builtin step", topic.name);
+                    sender
+                        .send(OperationPluginEvent::Update(operation_state))
+                        .await?
+                }
             }
             OperationAction::Script(script) => {
                 info!("Process operation event {}: using {script}", topic.name);
-                if let Ok(command) = Execute::try_new(&script) {
-                    let output = self.script_runner.await_response(command).await?;
-                    let new_state = operation_state.update_with_script_output(script, output);
-                    self.publish_operation_plugin_event(new_state).await?;
+                if let Ok(mut command) = Execute::try_new(&script) {
+                    // The current state is given to the script as its last argument
+                    command.args.push(operation_state.json.to_string());
+                    self.start_script(topic, script, command, operation_state);
                 } else {
                     error!("Fail to parse the command line: {script}");
                 }
@@ -137,12 +814,119 @@ impl OperationsActor {
         Ok(())
     }
 
+    /// Arm a timer for the current state of an operation, delegated to a child device
+    fn start_child_timer(&mut self, topic: String, timeout: u64) {
+        let Some(instance) = self.operations.get(&topic) else {
+            return;
+        };
+        let step = instance.step;
+        self.child_timers.push(tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_secs(timeout)).await;
+            (topic, step)
+        }));
+    }
+
+    fn start_script(
+        &mut self,
+        topic: Topic,
+        script: String,
+        command: Execute,
+        operation_state: OperationPluginMessage,
+    ) {
+        let (abort_handle, abort_registration) = AbortHandle::new_pair();
+        if let Some(instance) = self.operations.get_mut(&topic.name) {
+            instance.running_script = Some(abort_handle);
+        }
+        // The script process is killed when the task is aborted
+        let script_task = async move {
+            let output = execute(&command).await;
+            ScriptOutcome {
+                topic,
+                script,
+                operation_state,
+                output,
+            }
+        };
+        self.scripts.push(tokio::spawn(Abortable::new(
+            script_task,
+            abort_registration,
+        )));
+    }
+
+    /// Publish the new state computed by a script,
+    /// unless the operation moved meanwhile to a new state.
+    ///
+    /// The states received while the script was running are processed first:
+    /// the outcome of the script is discarded only if one of these states has been accepted.
+    async fn end_script(&mut self, outcome: ScriptOutcome) -> Result<(), ChannelError> {
+        let topic = outcome.topic.name.clone();
+        let Some(instance) = self.operations.get_mut(&topic) else {
+            warn!(
+                "Discard the outcome of {} for {topic}: the operation has been cleared",
+                outcome.script
+            );
+            return Ok(());
+        };
+        instance.running_script = None;
+        let pending = std::mem::take(&mut instance.pending);
+        let digest = instance.digest.clone();
+
+        for (topic, operation_state, recovered) in pending {
+            self.accept_operation_state(topic, operation_state, recovered)
+                .await?;
+        }
+
+        match self.operations.get(&topic) {
+            Some(instance) if instance.digest == digest => {
+                let new_state = outcome
+                    .operation_state
+                    .update_with_script_output(outcome.script, outcome.output)
+                    .with_step(instance.latest().1 + 1);
+                self.journal.record(TransitionSource::Script, &new_state);
+                self.publish_operation_plugin_event(new_state).await
+            }
+            _ => {
+                warn!(
+                    "Discard the outcome of {} for {topic}: the operation moved to a new state",
+                    outcome.script
+                );
+                Ok(())
+            }
+        }
+    }
+
+    /// The definition of the state of an operation, as given by the first workflow that declares it
+    fn get_state(&self, topic: &str, status: &str) -> Option<&OperationState> {
+        let topic = Topic::new_unchecked(topic);
+        self.workflows
+            .iter()
+            .filter(|(filter, _, _)| filter.accept_topic(&topic))
+            .find_map(|(_, workflow, _)| workflow.states.get(status))
+    }
+
+    /// Tell if some steps of an operation are delegated to the targeted child device,
+    /// the state of a given status being the one of the first matching workflow declaring it
+    fn is_delegated_to_child(&self, topic: &Topic) -> bool {
+        self.workflows
+            .iter()
+            .filter(|(filter, _, _)| filter.accept_topic(topic))
+            .flat_map(|(_, workflow, _)| workflow.states.keys())
+            .any(|status| {
+                self.get_state(&topic.name, status)
+                    .is_some_and(|state| state.owner == CHILD_OWNER)
+            })
+    }
+
     fn get_workflow_state(&self, topic: &Topic, status: &str) -> OperationAction {
         for (filter, workflow, maybe_sender) in self.workflows.iter() {
             if filter.accept_topic(topic) {
                 let maybe_state = workflow.states.get(status);
                 if let Some(state) = maybe_state {
-                    if &state.owner != "tedge" {
+                    if state.owner == CHILD_OWNER {
+                        let timeout = state.timeout.unwrap_or(DEFAULT_CHILD_TIMEOUT);
+                        return OperationAction::Child(timeout);
+                    }
+                    if state.owner != TEDGE_OWNER {
                         return OperationAction::External(state.owner.to_string());
                     }
                     if let Some(script) = &state.script {
@@ -161,6 +945,866 @@ impl OperationsActor {
 pub enum OperationAction {
     Unknown,
     External(String),
-    Internal(DynSender<OperationPluginMessage>),
+    Child(u64),
+    Internal(DynSender<OperationPluginEvent>),
     Script(String),
 }
+
+/// The terminal state of a cancelled operation
+const CANCELLED: &str = "cancelled";
+
+/// The terminal state of a failed operation
+const FAILED: &str = "failed";
+
+/// The owner of the states handled by thin-edge, and the publisher of the states it computes
+const TEDGE_OWNER: &str = "tedge";
+
+/// The owner of the states delegated to the child device targeted by an operation
+const CHILD_OWNER: &str = "child";
+
+/// The owner of the states handled by any external participant
+const EXTERNAL_OWNER: &str = "external";
+
+/// The delay, in seconds, after which a state delegated to a child device with no `timeout` is failed
+const DEFAULT_CHILD_TIMEOUT: u64 = 3600;
+
+/// The outcome of the check of the publisher of a new state
+#[derive(Debug, PartialEq, Eq)]
+enum PublisherCheck {
+    Accepted,
+
+    /// Accepted, the publisher being unknown
+    Flagged(String),
+
+    Rejected(String),
+}
+
+/// Check the publisher of a new state against the owner of the previous state.
+///
+/// - The states published by tedge, including those mirrored for a child device,
+///   are recognized by their digest: a payload can not claim to be published by `tedge`,
+///   nor by any other reserved owner name.
+/// - When the previous state is owned by `external`, the publisher has to be one of the registered owners of this state, if any.
+/// - When the previous state is owned by a named participant, the publisher has to be this participant.
+/// - A state without publisher is accepted but flagged, unless the previous state is owned by `external` with no registered owners,
+///   or is delegated to a `child` device, whose states are only accepted when mirrored by tedge.
+fn check_publisher(
+    owner: &str,
+    publisher: Option<&str>,
+    published_by_tedge: bool,
+    registered: &[&str],
+) -> PublisherCheck {
+    if published_by_tedge {
+        return PublisherCheck::Accepted;
+    }
+    let external = owner == EXTERNAL_OWNER;
+    match publisher {
+        Some(publisher) if OwnerRegistration::RESERVED_NAMES.contains(&publisher) => {
+            PublisherCheck::Rejected(format!("{publisher} is a reserved name"))
+        }
+        Some(publisher)
+            if external && (registered.is_empty() || registered.contains(&publisher)) =>
+        {
+            PublisherCheck::Accepted
+        }
+        Some(publisher) if publisher == owner => PublisherCheck::Accepted,
+        Some(publisher) => {
+            PublisherCheck::Rejected(format!("the state is published by {publisher}"))
+        }
+        None if external && registered.is_empty() => PublisherCheck::Accepted,
+        None if owner == CHILD_OWNER => PublisherCheck::Rejected(
+            "the state has no publisher, the child device having to respond on its side topic"
+                .to_string(),
+        ),
+        None => PublisherCheck::Flagged("the state has no publisher".to_string()),
+    }
+}
+
+/// The current state of an operation instance
+#[derive(Default)]
+struct OperationInstance {
+    /// The current status of the operation
+    status: String,
+
+    /// The step counter, incremented on each transition
+    step: u64,
+
+    /// The digest of the current state, used to detect duplicated messages:
+    /// the echoes of retained messages on reconnect and the QoS 1 redeliveries.
+    digest: String,
+
+    /// The digests of the states published by tedge for this operation,
+    /// the only states whose `step` is trusted.
+    ///
+    /// Only the digest of the terminal state is kept once the operation completed.
+    published: HashSet<String>,
+
+    /// The status and the step of the last state published by tedge for this operation,
+    /// possibly not received back yet
+    last_published: Option<(String, u64)>,
+
+    /// The current state of the operation
+    state: Option<OperationPluginMessage>,
+
+    /// Set while a script is running to move forward the operation
+    running_script: Option<AbortHandle>,
+
+    /// The states received while a script is running
+    pending: VecDeque<(Topic, OperationPluginMessage, bool)>,
+}
+
+impl OperationInstance {
+    /// The status and the step of the operation,
+    /// taking into account the last state published by tedge even if not received back yet
+    fn latest(&self) -> (&str, u64) {
+        match &self.last_published {
+            Some((status, step)) if *step > self.step => (status, *step),
+            _ => (&self.status, self.step),
+        }
+    }
+}
+
+struct ScriptOutcome {
+    topic: Topic,
+    script: String,
+    operation_state: OperationPluginMessage,
+    output: std::io::Result<Output>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::operations_sm::config::OperationKey;
+    use serde_json::{json, Value};
+    use std::os::unix::fs::PermissionsExt;
+    use tedge_actors::futures::channel::mpsc;
+    use tedge_actors::RuntimeRequest;
+    use tempfile::TempDir;
+    use PublisherCheck::*;
+
+    fn is_rejected(check: PublisherCheck) -> bool {
+        matches!(check, Rejected(_))
+    }
+
+    fn is_flagged(check: PublisherCheck) -> bool {
+        matches!(check, Flagged(_))
+    }
+
+    #[test]
+    fn accept_the_states_published_by_tedge() {
+        assert_eq!(check_publisher("tedge", Some("tedge"), true, &[]), Accepted);
+        assert_eq!(check_publisher("scheduler", None, true, &[]), Accepted);
+        assert_eq!(check_publisher("child", Some("tedge"), true, &[]), Accepted);
+    }
+
+    #[test]
+    fn reject_the_states_claiming_a_reserved_publisher() {
+        assert!(is_rejected(check_publisher(
+            "tedge",
+            Some("tedge"),
+            false,
+            &[]
+        )));
+        assert!(is_rejected(check_publisher(
+            "external",
+            Some("tedge"),
+            false,
+            &[]
+        )));
+        assert!(is_rejected(check_publisher(
+            "scheduler",
+            Some("tedge"),
+            false,
+            &[]
+        )));
+        assert!(is_rejected(check_publisher(
+            "child",
+            Some("child"),
+            false,
+            &[]
+        )));
+        assert!(is_rejected(check_publisher(
+            "external",
+            Some("external"),
+            false,
+            &[]
+        )));
+    }
+
+    #[test]
+    fn check_the_named_owners() {
+        assert_eq!(
+            check_publisher("scheduler", Some("scheduler"), false, &[]),
+            Accepted
+        );
+        assert!(is_rejected(check_publisher(
+            "scheduler",
+            Some("other"),
+            false,
+            &[]
+        )));
+        assert!(is_rejected(check_publisher(
+            "tedge",
+            Some("other"),
+            false,
+            &[]
+        )));
+        assert!(is_flagged(check_publisher("scheduler", None, false, &[])));
+        assert!(is_flagged(check_publisher("tedge", None, false, &[])));
+    }
+
+    #[test]
+    fn check_the_responses_of_a_child_device() {
+        assert_eq!(
+            check_publisher("child-1", Some("child-1"), false, &[]),
+            Accepted
+        );
+        assert!(is_rejected(check_publisher(
+            "child-1",
+            Some("child-2"),
+            false,
+            &[]
+        )));
+        assert!(is_rejected(check_publisher(
+            "child-1",
+            Some("tedge"),
+            false,
+            &[]
+        )));
+        assert!(is_flagged(check_publisher("child-1", None, false, &[])));
+    }
+
+    #[test]
+    fn reject_the_states_published_directly_while_delegated_to_a_child() {
+        assert_eq!(check_publisher("child", None, true, &[]), Accepted);
+        assert!(is_rejected(check_publisher("child", None, false, &[])));
+        assert!(is_rejected(check_publisher(
+            "child",
+            Some("child-1"),
+            false,
+            &[]
+        )));
+    }
+
+    #[test]
+    fn check_the_registered_owners_of_external_states() {
+        assert_eq!(
+            check_publisher("external", Some("any"), false, &[]),
+            Accepted
+        );
+        assert_eq!(check_publisher("external", None, false, &[]), Accepted);
+
+        let registered = ["scheduler"];
+        assert_eq!(
+            check_publisher("external", Some("scheduler"), false, &registered),
+            Accepted
+        );
+        assert!(is_rejected(check_publisher(
+            "external",
+            Some("other"),
+            false,
+            &registered
+        )));
+        assert!(is_flagged(check_publisher(
+            "external",
+            None,
+            false,
+            &registered
+        )));
+    }
+
+    const DEMO_WORKFLOW: &str = r#"
+        operation = "demo"
+        [init]
+        next = ["working"]
+        [working]
+        next = ["done", "failed"]
+        [done]
+        next = []
+        [failed]
+        next = []
+    "#;
+
+    const CANCELLABLE_WORKFLOW: &str = r#"
+        operation = "demo"
+        [init]
+        next = ["working", "cancelled"]
+        [working]
+        next = ["done", "failed", "cancelled"]
+        [done]
+        next = []
+        [failed]
+        next = []
+        [cancelled]
+        next = []
+    "#;
+
+    const CHILD_WORKFLOW: &str = r#"
+        operation = "demo"
+        [init]
+        next = ["working"]
+        [working]
+        owner = "child"
+        timeout = 1
+        next = ["done", "failed"]
+        [done]
+        next = []
+        [failed]
+        next = []
+    "#;
+
+    const CHILD_TOPIC: &str = "tedge/operations/child-1/demo/update/1";
+
+    const DEMO_TOPIC: &str = "tedge/operations/main-device/demo/update/1";
+
+    #[tokio::test]
+    async fn publish_the_plugin_states_emitted_before_the_echo_of_the_previous_one() {
+        let mut test = TestOperations::start(DEMO_WORKFLOW);
+
+        test.publish(json!({"status": "init"})).await;
+        assert_eq!(test.plugin_update().await["status"], "init");
+
+        // The plugin returns an intermediate state and then the outcome of a background task
+        test.plugin_state(json!({"status": "working"})).await;
+        test.plugin_state(json!({"status": "done"})).await;
+
+        let working = test.next_state().await;
+        assert_eq!(working["status"], "working");
+        assert_eq!(working["step"], 1);
+        let done = test.next_state().await;
+        assert_eq!(done["status"], "done");
+        assert_eq!(done["step"], 2);
+
+        // The echo of the intermediate state is not given back to the plugin
+        test.publish(working).await;
+        test.publish(done).await;
+        assert_eq!(test.plugin_update().await["status"], "done");
+    }
+
+    #[tokio::test]
+    async fn fail_an_operation_on_a_plugin_error_raised_after_an_intermediate_state() {
+        let mut test = TestOperations::start(DEMO_WORKFLOW);
+
+        test.publish(json!({"status": "init"})).await;
+        assert_eq!(test.plugin_update().await["status"], "init");
+
+        test.plugin_state(json!({"status": "working"})).await;
+        test.plugin_error("working", "task panicked").await;
+
+        assert_eq!(test.next_state().await["status"], "working");
+        let failed = test.next_state().await;
+        assert_eq!(failed["status"], "failed");
+        assert_eq!(failed["reason"], "task panicked");
+        assert_eq!(failed["step"], 2);
+    }
+
+    #[tokio::test]
+    async fn ignore_the_duplicated_states() {
+        let mut test = TestOperations::start(DEMO_WORKFLOW);
+
+        test.publish(json!({"status": "init"})).await;
+        assert_eq!(test.plugin_update().await["status"], "init");
+
+        // e.g. a QoS 1 redelivery
+        test.publish(json!({"status": "init"})).await;
+        test.assert_no_plugin_event().await;
+    }
+
+    #[tokio::test]
+    async fn ignore_the_states_received_out_of_order() {
+        let mut test = TestOperations::start(DEMO_WORKFLOW);
+
+        test.publish(json!({"status": "init"})).await;
+        assert_eq!(test.plugin_update().await["status"], "init");
+
+        test.plugin_state(json!({"status": "working"})).await;
+        let working = test.next_state().await;
+        test.publish(working.clone()).await;
+        assert_eq!(test.plugin_update().await["status"], "working");
+
+        test.plugin_state(json!({"status": "done"})).await;
+        let done = test.next_state().await;
+        test.publish(done).await;
+        assert_eq!(test.plugin_update().await["status"], "done");
+
+        // A late copy of the working state
+        test.publish(working).await;
+        test.assert_no_plugin_event().await;
+        test.assert_no_state().await;
+    }
+
+    #[tokio::test]
+    async fn process_the_states_received_while_a_script_runs_once_the_script_exited() {
+        let dir = tempfile::tempdir().unwrap();
+        let script = dir.path().join("check.sh");
+        std::fs::write(
+            &script,
+            "#!/bin/sh\nsleep 1\necho '{\"status\":\"working\"}'\n",
+        )
+        .unwrap();
+        std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let workflow = DEMO_WORKFLOW.replace(
+            "[init]\n",
+            &format!("[init]\n        script = \"{}\"\n", script.display()),
+        );
+        let mut test = TestOperations::start(&workflow);
+
+        test.publish(json!({"status": "init"})).await;
+        test.publish(json!({"status": "failed", "reason": "aborted"}))
+            .await;
+
+        // The new state is delayed till the script exits,
+        // the script outcome being then discarded, the operation having moved meanwhile
+        test.assert_no_plugin_event().await;
+        let failed = test.plugin_update().await;
+        assert_eq!(failed["status"], "failed");
+        assert_eq!(failed["reason"], "aborted");
+        test.assert_no_state().await;
+    }
+
+    #[tokio::test]
+    async fn cancel_an_operation_while_its_script_is_running() {
+        let dir = tempfile::tempdir().unwrap();
+        let script = dir.path().join("check.sh");
+        let done = dir.path().join("done");
+        std::fs::write(
+            &script,
+            format!(
+                "#!/bin/sh\nsleep 1\ntouch {}\necho '{{\"status\":\"working\"}}'\n",
+                done.display()
+            ),
+        )
+        .unwrap();
+        std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let workflow = CANCELLABLE_WORKFLOW.replace(
+            "[init]\n",
+            &format!("[init]\n        script = \"{}\"\n", script.display()),
+        );
+        let mut test = TestOperations::start(&workflow);
+
+        test.publish(json!({"status": "init"})).await;
+        test.cancel("no more needed").await;
+
+        let cancelled = test.next_state().await;
+        assert_eq!(cancelled["status"], "cancelled");
+        assert_eq!(cancelled["reason"], "no more needed");
+
+        // The script has been killed
+        test.assert_no_state().await;
+        assert!(!done.exists());
+    }
+
+    #[tokio::test]
+    async fn cancel_an_operation_in_a_step_owned_by_a_plugin() {
+        let mut test = TestOperations::start(CANCELLABLE_WORKFLOW);
+
+        test.publish(json!({"status": "init"})).await;
+        assert_eq!(test.plugin_update().await["status"], "init");
+        test.cancel("no more needed").await;
+
+        let cancelled = test.next_state().await;
+        assert_eq!(cancelled["status"], "cancelled");
+        assert_eq!(cancelled["step"], 1);
+        match test.plugin_event().await {
+            OperationPluginEvent::Cancel(state) => assert_eq!(state.status, "init"),
+            event => panic!("Unexpected plugin event: {event:?}"),
+        }
+
+        // The plugin outcome for the cancelled step is discarded
+        test.publish(cancelled).await;
+        test.plugin_state(json!({"status": "working"})).await;
+        test.assert_no_state().await;
+    }
+
+    #[tokio::test]
+    async fn reject_the_cancel_requests_when_the_workflow_has_no_cancelled_transition() {
+        let mut test = TestOperations::start(DEMO_WORKFLOW);
+
+        test.publish(json!({"status": "init"})).await;
+        assert_eq!(test.plugin_update().await["status"], "init");
+        test.cancel("no more needed").await;
+
+        test.assert_no_plugin_event().await;
+        test.assert_no_state().await;
+    }
+
+    #[tokio::test]
+    async fn recover_the_operations_in_a_step_owned_by_a_plugin() {
+        let mut test = TestOperations::start(DEMO_WORKFLOW);
+
+        test.recover(json!({"status": "working", "step": 3, "publisher": "tedge"}))
+            .await;
+        match test.plugin_event().await {
+            OperationPluginEvent::Recover(state) => assert_eq!(state.status, "working"),
+            event => panic!("Unexpected plugin event: {event:?}"),
+        }
+
+        // The steps are numbered from the recovered state
+        test.plugin_state(json!({"status": "done"})).await;
+        assert_eq!(test.next_state().await["step"], 4);
+    }
+
+    #[tokio::test]
+    async fn run_again_the_script_of_a_recovered_operation() {
+        let dir = tempfile::tempdir().unwrap();
+        let script = dir.path().join("work.sh");
+        std::fs::write(&script, "#!/bin/sh\necho '{\"status\":\"done\"}'\n").unwrap();
+        std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let workflow = DEMO_WORKFLOW.replace(
+            "[working]\n",
+            &format!("[working]\n        script = \"{}\"\n", script.display()),
+        );
+        let mut test = TestOperations::start(&workflow);
+
+        test.recover(json!({"status": "working", "step": 3, "publisher": "tedge"}))
+            .await;
+
+        let done = test.next_state().await;
+        assert_eq!(done["status"], "done");
+        assert_eq!(done["step"], 4);
+        test.assert_no_plugin_event().await;
+    }
+
+    #[tokio::test]
+    async fn mirror_the_responses_of_a_child_device() {
+        let mut test = TestOperations::start_on(CHILD_WORKFLOW, CHILD_TOPIC);
+        let request_topic = format!("{CHILD_TOPIC}/child/request");
+
+        test.publish(json!({"status": "init"})).await;
+        assert_eq!(test.plugin_update().await["status"], "init");
+        test.plugin_state(json!({"status": "working"})).await;
+        let working = test.next_state().await;
+        test.publish(working).await;
+
+        let request = test.next_message(&request_topic).await;
+        assert!(request.retain);
+        let request: Value = serde_json::from_str(request.payload_str().unwrap()).unwrap();
+        assert_eq!(request["status"], "working");
+
+        // A response from another device is rejected
+        test.child_response(json!({"status": "done", "publisher": "child-2"}))
+            .await;
+        test.child_response(json!({"status": "done", "publisher": "child-1"}))
+            .await;
+        let done = test.next_state().await;
+        assert_eq!(done["status"], "done");
+        assert_eq!(done["step"], 2);
+        assert_eq!(done["publisher"], "tedge");
+
+        // The retained request is cleared once the operation completed
+        test.publish(done).await;
+        let clear = test.next_message(&request_topic).await;
+        assert!(clear.retain);
+        assert!(clear.payload_bytes().is_empty());
+    }
+
+    #[tokio::test]
+    async fn fail_the_operation_when_the_child_device_does_not_respond() {
+        let mut test = TestOperations::start_on(CHILD_WORKFLOW, CHILD_TOPIC);
+
+        test.publish(json!({"status": "init"})).await;
+        assert_eq!(test.plugin_update().await["status"], "init");
+        test.plugin_state(json!({"status": "working"})).await;
+        let working = test.next_state().await;
+        test.publish(working).await;
+
+        let failed = test.next_state().await;
+        assert_eq!(failed["status"], "failed");
+        assert_eq!(
+            failed["reason"],
+            "The child device child-1 did not respond in time"
+        );
+        assert_eq!(failed["step"], 2);
+    }
+
+    #[tokio::test]
+    async fn reject_the_states_published_on_the_operation_topic_while_delegated_to_a_child() {
+        let mut test = TestOperations::start_on(CHILD_WORKFLOW, CHILD_TOPIC);
+
+        test.publish(json!({"status": "init"})).await;
+        assert_eq!(test.plugin_update().await["status"], "init");
+        test.plugin_state(json!({"status": "working"})).await;
+        let working = test.next_state().await;
+        test.publish(working).await;
+
+        test.publish(json!({"status": "done"})).await;
+        test.assert_no_plugin_event().await;
+    }
+
+    #[test]
+    fn apply_a_default_timeout_to_the_states_delegated_to_a_child() {
+        let workflow = CHILD_WORKFLOW.replace("timeout = 1\n", "");
+        let workflow: OperationWorkflow = toml::from_str(&workflow).unwrap();
+        let filter = TopicFilter::try_from(&workflow.filter).unwrap();
+        let (_, input_receiver) = mpsc::channel(1);
+        let (_, signal_receiver) = mpsc::channel(1);
+        let (mqtt_sender, _) = mpsc::channel(1);
+        let actor = OperationsActor::new(
+            LoggingReceiver::new("Operations".to_string(), input_receiver, signal_receiver),
+            mqtt_sender.into(),
+            Journal::default(),
+            vec![(filter, workflow, None)],
+        );
+
+        let topic = Topic::new_unchecked(CHILD_TOPIC);
+        assert!(matches!(
+            actor.get_workflow_state(&topic, "working"),
+            OperationAction::Child(DEFAULT_CHILD_TIMEOUT)
+        ));
+    }
+
+    #[tokio::test]
+    async fn fail_the_operations_of_an_external_owner_that_stopped_its_heartbeat() {
+        let workflow =
+            DEMO_WORKFLOW.replace("[working]\n", "[working]\n        owner = \"scheduler\"\n");
+        let mut test = TestOperations::start(&workflow);
+        test.register_owner(
+            "scheduler",
+            json!({"operation": "demo", "states": ["working"], "heartbeat": 1}),
+        )
+        .await;
+
+        test.publish(json!({"status": "init"})).await;
+        assert_eq!(test.plugin_update().await["status"], "init");
+        test.plugin_state(json!({"status": "working"})).await;
+        let working = test.next_state().await;
+        test.publish(working).await;
+
+        // No heartbeat is sent within the grace period of two intervals
+        let failed = test.next_state().await;
+        assert_eq!(failed["status"], "failed");
+        assert_eq!(
+            failed["reason"],
+            "The external owner scheduler is no longer alive"
+        );
+        assert_eq!(failed["step"], 2);
+    }
+
+    #[tokio::test]
+    async fn accept_a_new_request_on_the_topic_of_a_terminated_operation() {
+        let mut test = TestOperations::start(DEMO_WORKFLOW);
+
+        test.publish(json!({"status": "init"})).await;
+        assert_eq!(test.plugin_update().await["status"], "init");
+        test.plugin_state(json!({"status": "working"})).await;
+        let working = test.next_state().await;
+        test.publish(working.clone()).await;
+        assert_eq!(test.plugin_update().await["status"], "working");
+        test.plugin_state(json!({"status": "done"})).await;
+        let done = test.next_state().await;
+        test.publish(done).await;
+        assert_eq!(test.plugin_update().await["status"], "done");
+
+        // A late copy of a state published by tedge is not a new request
+        test.publish(working).await;
+        test.assert_no_plugin_event().await;
+
+        // The topic has not been cleared, but a new request can be published by the mapper
+        test.publish(json!({"status": "init", "publisher": "mapper"}))
+            .await;
+        let init = test.plugin_update().await;
+        assert_eq!(init["status"], "init");
+        test.plugin_state(json!({"status": "working"})).await;
+        assert_eq!(test.next_state().await["step"], 1);
+    }
+
+    #[tokio::test]
+    async fn journal_each_transition_once() {
+        let mut test = TestOperations::start(DEMO_WORKFLOW);
+
+        test.publish(json!({"status": "init"})).await;
+        assert_eq!(test.plugin_update().await["status"], "init");
+        test.plugin_state(json!({"status": "working"})).await;
+        let working = test.next_state().await;
+        test.publish(working).await;
+        assert_eq!(test.plugin_update().await["status"], "working");
+
+        assert_eq!(
+            test.history(2).await,
+            vec![
+                (TransitionSource::Incoming, "init".to_string()),
+                (TransitionSource::Plugin, "working".to_string())
+            ]
+        );
+    }
+
+    /// An operations actor, running a workflow whose plugin is played by the test
+    struct TestOperations {
+        topic: String,
+        input: mpsc::Sender<OperationInput>,
+        mqtt: mpsc::Receiver<MqttMessage>,
+        plugin: mpsc::Receiver<OperationPluginEvent>,
+        _signal: mpsc::Sender<RuntimeRequest>,
+        journal_dir: TempDir,
+    }
+
+    impl TestOperations {
+        fn start(workflow: &str) -> Self {
+            TestOperations::start_on(workflow, DEMO_TOPIC)
+        }
+
+        /// Start the actor, the test playing the operation published on the given topic
+        fn start_on(workflow: &str, topic: &str) -> Self {
+            let workflow: OperationWorkflow = toml::from_str(workflow).unwrap();
+            let filter = TopicFilter::try_from(&workflow.filter).unwrap();
+            let (input, input_receiver) = mpsc::channel(16);
+            let (signal, signal_receiver) = mpsc::channel(1);
+            let (mqtt_sender, mqtt) = mpsc::channel(16);
+            let (plugin_sender, plugin) = mpsc::channel(16);
+            let journal_dir = tempfile::tempdir().unwrap();
+            let journal = Journal::new(journal_dir.path().join("operations.log"), 1 << 20, 1);
+
+            let mut actor = OperationsActor::new(
+                LoggingReceiver::new("Operations".to_string(), input_receiver, signal_receiver),
+                mqtt_sender.into(),
+                journal,
+                vec![(filter, workflow, Some(plugin_sender.into()))],
+            );
+            tokio::spawn(async move { actor.run().await });
+
+            TestOperations {
+                topic: topic.to_string(),
+                input,
+                mqtt,
+                plugin,
+                _signal: signal,
+                journal_dir,
+            }
+        }
+
+        /// Publish a state on the operation topic, as done by tedge, a child device or an external owner
+        async fn publish(&mut self, state: Value) {
+            let message = MqttMessage::new(&Topic::new_unchecked(&self.topic), state.to_string());
+            self.send(OperationInput::MqttMessage(message)).await
+        }
+
+        /// Request the operation to be cancelled
+        async fn cancel(&mut self, reason: &str) {
+            let topic = Topic::new_unchecked(&format!("{}/cancel", self.topic));
+            let message = MqttMessage::new(&topic, json!({ "reason": reason }).to_string());
+            self.send(OperationInput::MqttMessage(message)).await
+        }
+
+        /// Replay a state retained by the broker, as received on start
+        async fn recover(&mut self, state: Value) {
+            let message = MqttMessage::new(&Topic::new_unchecked(&self.topic), state.to_string())
+                .with_retain();
+            self.send(OperationInput::MqttMessage(message)).await
+        }
+
+        /// Publish the response of a child device, on the side topic of the operation
+        async fn child_response(&mut self, state: Value) {
+            let topic = Topic::new_unchecked(&format!("{}/child/response", self.topic));
+            let message = MqttMessage::new(&topic, state.to_string());
+            self.send(OperationInput::MqttMessage(message)).await
+        }
+
+        /// Register an out-of-process plugin as the owner of some states
+        async fn register_owner(&mut self, name: &str, registration: Value) {
+            let topic = Topic::new_unchecked(&format!("{}{name}", OwnerRegistration::TOPIC_PREFIX));
+            let message = MqttMessage::new(&topic, registration.to_string()).with_retain();
+            self.send(OperationInput::MqttMessage(message)).await
+        }
+
+        /// Return a new state computed by the plugin for the operation
+        async fn plugin_state(&mut self, state: Value) {
+            let operation = OperationKey::try_from(&self.topic).unwrap();
+            let status = state["status"].as_str().unwrap().to_string();
+            let event = OperationPluginMessage::new(operation, status, state);
+            self.send(OperationInput::OperationPluginMessage(event))
+                .await
+        }
+
+        /// Report a plugin error, raised for the operation in the given status
+        async fn plugin_error(&mut self, status: &str, reason: &str) {
+            let error = OperationPluginError {
+                operation: OperationKey::try_from(&self.topic).unwrap(),
+                status: status.to_string(),
+                reason: reason.to_string(),
+            };
+            self.send(OperationInput::OperationPluginError(error)).await
+        }
+
+        async fn send(&mut self, input: OperationInput) {
+            self.input.send(input).await.unwrap()
+        }
+
+        /// The next state published by the actor on the operation topic
+        async fn next_state(&mut self) -> Value {
+            let topic = self.topic.clone();
+            let message = self.next_message(&topic).await;
+            serde_json::from_str(message.payload_str().unwrap()).unwrap()
+        }
+
+        /// The next message published by the actor on a topic, skipping the messages on other topics
+        async fn next_message(&mut self, topic: &str) -> MqttMessage {
+            loop {
+                let message = tokio::time::timeout(Duration::from_secs(5), self.mqtt.next())
+                    .await
+                    .expect("a message to be published")
+                    .unwrap();
+                if message.topic.name == topic {
+                    return message;
+                }
+            }
+        }
+
+        /// The journaled transitions of the operation, once the expected count written
+        async fn history(&self, count: usize) -> Vec<(TransitionSource, String)> {
+            let path = self.journal_dir.path().join("operations.log");
+            let journal = Journal::new(path, 1 << 20, 1);
+            for _ in 0..50 {
+                if journal.history(&self.topic).unwrap().len() >= count {
+                    break;
+                }
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+            // Let a transition journaled twice be written too
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            journal
+                .history(&self.topic)
+                .unwrap()
+                .into_iter()
+                .map(|entry| (entry.source, entry.status))
+                .collect()
+        }
+
+        /// Check that no state is published on the operation topic
+        async fn assert_no_state(&mut self) {
+            let deadline = tokio::time::Instant::now() + Duration::from_millis(1500);
+            while let Ok(Some(message)) = tokio::time::timeout_at(deadline, self.mqtt.next()).await
+            {
+                assert_ne!(
+                    message.topic.name,
+                    self.topic,
+                    "Unexpected state: {:?}",
+                    message.payload_str()
+                );
+            }
+        }
+
+        /// Check that no event is sent to the plugin
+        async fn assert_no_plugin_event(&mut self) {
+            let timeout = Duration::from_millis(200);
+            if let Ok(Some(event)) = tokio::time::timeout(timeout, self.plugin.next()).await {
+                panic!("Unexpected plugin event: {event:?}");
+            }
+        }
+
+        /// The next event sent to the plugin
+        async fn plugin_event(&mut self) -> OperationPluginEvent {
+            tokio::time::timeout(Duration::from_secs(5), self.plugin.next())
+                .await
+                .expect("an event to be sent to the plugin")
+                .unwrap()
+        }
+
+        /// The state of the next update event sent to the plugin
+        async fn plugin_update(&mut self) -> Value {
+            match self.plugin_event().await {
+                OperationPluginEvent::Update(state) => state.json,
+                event => panic!("Unexpected plugin event: {event:?}"),
+            }
+        }
+    }
+}
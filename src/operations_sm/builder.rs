@@ -1,51 +1,62 @@
 use crate::operations_sm::actor::OperationsActor;
-use crate::operations_sm::config::OperationWorkflow;
-use crate::operations_sm::messages::{OperationInput, OperationPluginMessage};
-use log::error;
+use crate::operations_sm::config::{OperationFilter, OperationWorkflow};
+use crate::operations_sm::journal::Journal;
+use crate::operations_sm::messages::{
+    OperationInput, OperationPluginError, OperationPluginEvent, OperationPluginMessage,
+};
+use crate::operations_sm::plugin::unhandled_states;
+use log::{error, warn};
 use std::convert::Infallible;
-use std::process::Output;
 use tedge_actors::{
-    adapt, Builder, ClientMessageBox, DynSender, LoggingReceiver, Message, NoConfig,
-    RuntimeRequest, RuntimeRequestSink, ServiceProvider,
+    adapt, Builder, DynSender, LoggingReceiver, Message, RuntimeRequest, RuntimeRequestSink,
+    ServiceProvider,
 };
 use tedge_mqtt_ext::{MqttMessage, TopicFilter};
 
 pub struct OperationsActorBuilder {
     input_receiver: LoggingReceiverBuilder<OperationInput>,
     mqtt_sender: DynSender<MqttMessage>,
-    script_runner: ClientMessageBox<Execute, std::io::Result<Output>>,
+    journal: Journal,
 
     /// All the operation workflow definitions,
     /// possibly with a channel to the actor operation plugin that implement the workflow
     workflows: Vec<(
         TopicFilter,
         OperationWorkflow,
-        Option<DynSender<OperationPluginMessage>>,
+        Option<DynSender<OperationPluginEvent>>,
     )>,
+
+    /// The states processed by the operation plugins, used to check the custom workflows
+    plugin_states: Vec<(OperationFilter, Vec<String>)>,
 }
 
 impl OperationsActorBuilder {
     pub fn new(
         mqtt: &mut impl ServiceProvider<MqttMessage, MqttMessage, TopicFilter>,
-        script_runner: &mut impl ServiceProvider<Execute, std::io::Result<Output>, NoConfig>,
+        journal: Journal,
     ) -> Self {
         let input_receiver = LoggingReceiverBuilder::new(OperationsActor::name());
         let input_sender = adapt(&input_receiver.get_input_sender());
         let mqtt_sender = mqtt.connect_consumer(OperationsActor::subscriptions(), input_sender);
-        let script_runner = ClientMessageBox::new("Operation Script Runner", script_runner);
         let workflows = Vec::new();
 
         OperationsActorBuilder {
             input_receiver,
             mqtt_sender,
-            script_runner,
+            journal,
             workflows,
+            plugin_states: Vec::new(),
         }
     }
 
+    /// The channel used by the operation plugins to report the errors that have to fail an operation
+    pub fn get_error_sender(&self) -> DynSender<OperationPluginError> {
+        adapt(&self.input_receiver.get_input_sender())
+    }
+
     pub fn register_operation_plugin(
         &mut self,
-        sender: DynSender<OperationPluginMessage>,
+        sender: DynSender<OperationPluginEvent>,
         workflow: OperationWorkflow,
     ) {
         let filter = &workflow.filter.clone();
@@ -54,6 +65,12 @@ impl OperationsActorBuilder {
         }
     }
 
+    /// Record the states processed by an operation plugin
+    pub fn register_plugin_states(&mut self, filter: OperationFilter, states: &[&str]) {
+        let states = states.iter().map(|status| status.to_string()).collect();
+        self.plugin_states.push((filter, states));
+    }
+
     pub fn register_custom_workflow(&mut self, workflow: OperationWorkflow) {
         let filter = &workflow.filter.clone();
         if let Err(err) = self.register_workflow(workflow, None) {
@@ -64,22 +81,45 @@ impl OperationsActorBuilder {
     pub fn register_workflow(
         &mut self,
         workflow: OperationWorkflow,
-        sender: Option<DynSender<OperationPluginMessage>>,
+        sender: Option<DynSender<OperationPluginEvent>>,
     ) -> Result<(), String> {
         let filter = &workflow.filter;
         let topic = filter.try_into()?;
         self.workflows.push((topic, workflow, sender));
         Ok(())
     }
+
+    /// Warn on the non-terminal states of a custom workflow that are owned by tedge without a script,
+    /// while no plugin can process them.
+    fn check_custom_workflows(&self) {
+        for (_, workflow, sender) in self.workflows.iter() {
+            if sender.is_some() {
+                continue;
+            }
+            let plugin_states: Vec<&str> = self
+                .plugin_states
+                .iter()
+                .filter(|(filter, _)| filter.overlaps(&workflow.filter))
+                .flat_map(|(_, states)| states.iter().map(String::as_str))
+                .collect();
+            for status in unhandled_states(workflow, &plugin_states) {
+                warn!(
+                    "The workflow for {:?} declares the state {status} as owned by tedge without a script, \
+                     but no plugin can process it",
+                    workflow.filter
+                );
+            }
+        }
+    }
 }
 
-impl ServiceProvider<OperationPluginMessage, OperationPluginMessage, OperationWorkflow>
+impl ServiceProvider<OperationPluginMessage, OperationPluginEvent, OperationWorkflow>
     for OperationsActorBuilder
 {
     fn connect_consumer(
         &mut self,
         config: OperationWorkflow,
-        response_sender: DynSender<OperationPluginMessage>,
+        response_sender: DynSender<OperationPluginEvent>,
     ) -> DynSender<OperationPluginMessage> {
         self.register_operation_plugin(response_sender, config);
         adapt(&self.input_receiver.get_input_sender())
@@ -96,10 +136,11 @@ impl Builder<OperationsActor> for OperationsActorBuilder {
     type Error = Infallible;
 
     fn try_build(self) -> Result<OperationsActor, Self::Error> {
+        self.check_custom_workflows();
         Ok(OperationsActor::new(
             self.input_receiver.build(),
             self.mqtt_sender,
-            self.script_runner,
+            self.journal,
             self.workflows,
         ))
     }
@@ -110,7 +151,6 @@ impl Builder<OperationsActor> for OperationsActorBuilder {
 // ----------------
 
 use tedge_actors::futures::channel::mpsc;
-use tedge_script_ext::Execute;
 
 struct LoggingReceiverBuilder<M: Message> {
     receiver: LoggingReceiver<M>,
@@ -117,6 +117,21 @@ pub struct OperationFilter {
     pub request: Option<String>,
 }
 
+impl OperationFilter {
+    /// Check if some operation instances are in the scope of both filters
+    pub fn overlaps(&self, other: &OperationFilter) -> bool {
+        fn overlaps(lhs: &Option<String>, rhs: &Option<String>) -> bool {
+            match (lhs, rhs) {
+                (Some(lhs), Some(rhs)) => lhs == rhs,
+                _ => true,
+            }
+        }
+        overlaps(&self.subsystem, &other.subsystem)
+            && overlaps(&self.operation, &other.operation)
+            && overlaps(&self.request, &other.request)
+    }
+}
+
 impl TryFrom<&OperationFilter> for TopicFilter {
     type Error = String;
 
@@ -149,13 +164,19 @@ pub struct OperationWorkflow {
 pub struct OperationState {
     /// The workflow participant that is responsible on moving forward the operation when in that state
     /// - tedge
-    /// - external
+    /// - child, for a state delegated to the child device targeted by the operation
+    /// - external, for a state handled by any external participant
+    /// - the name of an external participant, the only one allowed to publish the next state
     #[serde(default = "tedge_owner")]
     pub owner: String,
 
     /// Possibly a script to handle the operation when in that state
     pub script: Option<String>,
 
+    /// Possibly a delay, in seconds, after which a state delegated to a child device is failed,
+    /// one hour by default
+    pub timeout: Option<u64>,
+
     /// Transitions
     pub next: Vec<String>,
 }
@@ -165,6 +186,7 @@ impl Default for OperationState {
         OperationState {
             owner: tedge_owner(),
             script: None,
+            timeout: None,
             next: vec![],
         }
     }
@@ -0,0 +1,316 @@
+use crate::operations_sm::messages::OperationPluginMessage;
+use log::error;
+use serde::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
+
+pub const DEFAULT_JOURNAL_PATH: &str = "./journal/operations.log";
+pub const DEFAULT_JOURNAL_MAX_SIZE: u64 = 1024 * 1024;
+pub const DEFAULT_JOURNAL_MAX_FILES: usize = 5;
+
+/// The participant that triggered a transition
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TransitionSource {
+    /// A state received over MQTT
+    Incoming,
+
+    /// A state computed by a workflow script
+    Script,
+
+    /// A state emitted by an operation plugin
+    Plugin,
+
+    /// An operation failed on a plugin error
+    PluginError,
+
+    /// A cancellation requested over MQTT
+    Cancel,
+
+    /// A state published by a child device
+    Child,
+
+    /// A state delegated to a child device that timed out
+    Timeout,
+
+    /// A state owned by an external owner that is no longer alive
+    OwnerLost,
+}
+
+/// A line of the journal
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct JournalEntry {
+    /// When the transition has been handled, in RFC 3339 format
+    pub timestamp: String,
+
+    /// Who triggered the transition
+    pub source: TransitionSource,
+
+    /// The operation topic
+    pub topic: String,
+
+    /// The new status of the operation
+    pub status: String,
+
+    /// The sha256 digest of the JSON payload
+    pub digest: String,
+}
+
+impl JournalEntry {
+    pub fn new(source: TransitionSource, message: &OperationPluginMessage) -> Self {
+        let timestamp = OffsetDateTime::now_utc()
+            .format(&Rfc3339)
+            .unwrap_or_else(|_| "unknown".to_string());
+        JournalEntry {
+            timestamp,
+            source,
+            topic: (&message.operation).into(),
+            status: message.status.clone(),
+            digest: message.digest(),
+        }
+    }
+}
+
+/// An append-only journal of all the transitions handled by the operations actor.
+///
+/// The entries are appended as JSON lines to a file,
+/// which is rotated when its size exceeds `max_size`.
+/// The rotated files are suffixed by an index, `.1` being the most recent,
+/// and only `max_files` such files are kept.
+///
+/// The files are written by a blocking task, so recording a transition never blocks the actor.
+pub struct Journal {
+    files: JournalFiles,
+
+    /// The channel to the writer task, started on the first recorded transition
+    writer: Option<UnboundedSender<JournalEntry>>,
+}
+
+/// The files of a journal
+#[derive(Clone)]
+struct JournalFiles {
+    path: PathBuf,
+    max_size: u64,
+    max_files: usize,
+}
+
+impl Default for Journal {
+    fn default() -> Self {
+        Journal::new(
+            DEFAULT_JOURNAL_PATH,
+            DEFAULT_JOURNAL_MAX_SIZE,
+            DEFAULT_JOURNAL_MAX_FILES,
+        )
+    }
+}
+
+impl Journal {
+    pub fn new(path: impl AsRef<Path>, max_size: u64, max_files: usize) -> Self {
+        Journal {
+            files: JournalFiles {
+                path: path.as_ref().to_path_buf(),
+                max_size,
+                max_files,
+            },
+            writer: None,
+        }
+    }
+
+    /// Record a transition, logging any error rather than failing the caller.
+    ///
+    /// The transition is written in the background, and has to be recorded from a tokio runtime.
+    pub fn record(&mut self, source: TransitionSource, message: &OperationPluginMessage) {
+        let entry = JournalEntry::new(source, message);
+        let writer = self
+            .writer
+            .get_or_insert_with(|| self.files.clone().start_writer());
+        if writer.send(entry).is_err() {
+            error!(
+                "Fail to journal the transition of {:?} to {}: the writer stopped",
+                self.files.path, message.status
+            );
+        }
+    }
+
+    /// Return all the journaled transitions of an operation, from the oldest to the most recent
+    pub fn history(&self, topic: &str) -> std::io::Result<Vec<JournalEntry>> {
+        self.files.history(topic)
+    }
+}
+
+impl JournalFiles {
+    /// Append the entries sent over the returned channel, till this channel is closed
+    fn start_writer(self) -> UnboundedSender<JournalEntry> {
+        let (sender, mut receiver) = unbounded_channel::<JournalEntry>();
+        tokio::task::spawn_blocking(move || {
+            while let Some(entry) = receiver.blocking_recv() {
+                if let Err(err) = self.append(&entry) {
+                    error!(
+                        "Fail to journal the transition of {:?} to {}: {err}",
+                        self.path, entry.status
+                    );
+                }
+            }
+        });
+        sender
+    }
+
+    fn append(&self, entry: &JournalEntry) -> std::io::Result<()> {
+        let mut line = serde_json::to_string(entry)?;
+        line.push('\n');
+
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let size = std::fs::metadata(&self.path).map(|m| m.len()).unwrap_or(0);
+        if size > 0 && size + line.len() as u64 > self.max_size {
+            self.rotate()?;
+        }
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        file.write_all(line.as_bytes())
+    }
+
+    fn history(&self, topic: &str) -> std::io::Result<Vec<JournalEntry>> {
+        let mut entries = Vec::new();
+        for path in self.files().into_iter().rev() {
+            let file = match File::open(&path) {
+                Ok(file) => file,
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(err) => return Err(err),
+            };
+            for line in BufReader::new(file).lines() {
+                match serde_json::from_str::<JournalEntry>(&line?) {
+                    Ok(entry) if entry.topic == topic => entries.push(entry),
+                    Ok(_) => {}
+                    Err(err) => error!("Ignore invalid journal entry in {:?}: {err}", path),
+                }
+            }
+        }
+        Ok(entries)
+    }
+
+    /// The journal files, from the most recent to the oldest
+    fn files(&self) -> Vec<PathBuf> {
+        let mut files = vec![self.path.clone()];
+        files.extend((1..=self.max_files).map(|i| self.rotated_path(i)));
+        files
+    }
+
+    fn rotated_path(&self, index: usize) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{index}"));
+        path.into()
+    }
+
+    fn rotate(&self) -> std::io::Result<()> {
+        if self.max_files == 0 {
+            return std::fs::remove_file(&self.path);
+        }
+        let oldest = self.rotated_path(self.max_files);
+        if oldest.exists() {
+            std::fs::remove_file(&oldest)?;
+        }
+        for index in (1..self.max_files).rev() {
+            let path = self.rotated_path(index);
+            if path.exists() {
+                std::fs::rename(&path, self.rotated_path(index + 1))?;
+            }
+        }
+        std::fs::rename(&self.path, self.rotated_path(1))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::operations_sm::config::OperationKey;
+    use serde_json::json;
+    use std::time::Duration;
+
+    const TOPIC: &str = "tedge/operations/main-device/configuration/update/1";
+
+    fn message(topic: &str, status: &str) -> OperationPluginMessage {
+        let operation = OperationKey::try_from(&topic.to_string()).unwrap();
+        OperationPluginMessage::new(operation, status.to_string(), json!({}))
+    }
+
+    fn entry(topic: &str, status: &str) -> JournalEntry {
+        JournalEntry::new(TransitionSource::Incoming, &message(topic, status))
+    }
+
+    fn statuses(entries: Vec<JournalEntry>) -> Vec<String> {
+        entries.into_iter().map(|entry| entry.status).collect()
+    }
+
+    #[test]
+    fn list_the_transitions_of_an_operation() {
+        let dir = tempfile::tempdir().unwrap();
+        let files = Journal::new(dir.path().join("operations.log"), 1 << 20, 1).files;
+        let other = "tedge/operations/main-device/configuration/update/2";
+
+        files.append(&entry(TOPIC, "init")).unwrap();
+        files.append(&entry(other, "init")).unwrap();
+        files.append(&entry(TOPIC, "scheduled")).unwrap();
+
+        assert_eq!(
+            statuses(files.history(TOPIC).unwrap()),
+            ["init", "scheduled"]
+        );
+        assert_eq!(statuses(files.history(other).unwrap()), ["init"]);
+    }
+
+    #[test]
+    fn rotate_the_journal_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("operations.log");
+        let line_size = serde_json::to_string(&entry(TOPIC, "s1")).unwrap().len() as u64 + 1;
+        // Room for 2 lines per file, with some slack for the timestamps
+        let files = Journal::new(&path, 2 * line_size + line_size / 2, 2).files;
+
+        for status in ["s1", "s2", "s3", "s4", "s5", "s6", "s7"] {
+            files.append(&entry(TOPIC, status)).unwrap();
+        }
+
+        // Only the 2 most recent rotated files are kept, along the current file:
+        // the oldest file, with s1 and s2, has been removed
+        assert!(dir.path().join("operations.log.1").exists());
+        assert!(dir.path().join("operations.log.2").exists());
+        assert!(!dir.path().join("operations.log.3").exists());
+        assert_eq!(
+            statuses(files.history(TOPIC).unwrap()),
+            ["s3", "s4", "s5", "s6", "s7"]
+        );
+    }
+
+    #[tokio::test]
+    async fn record_the_transitions_in_the_background() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut journal = Journal::new(
+            dir.path().join("journal").join("operations.log"),
+            1 << 20,
+            1,
+        );
+
+        journal.record(TransitionSource::Incoming, &message(TOPIC, "init"));
+        journal.record(TransitionSource::Plugin, &message(TOPIC, "scheduled"));
+
+        let mut history = vec![];
+        for _ in 0..50 {
+            history = journal.history(TOPIC).unwrap();
+            if history.len() == 2 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert_eq!(statuses(history.clone()), ["init", "scheduled"]);
+        assert_eq!(history[1].source, TransitionSource::Plugin);
+    }
+}
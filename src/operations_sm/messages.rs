@@ -1,9 +1,83 @@
 use crate::operations_sm::config::OperationKey;
 use log::info;
 use serde_json::Value;
+use sha2::{Digest, Sha256};
 use tedge_actors::fan_in_message_type;
-use tedge_mqtt_ext::{MqttMessage, QoS};
-fan_in_message_type!(OperationInput[MqttMessage, OperationPluginMessage]: Debug);
+use tedge_mqtt_ext::{MqttMessage, QoS, Topic};
+fan_in_message_type!(OperationInput[MqttMessage, OperationPluginMessage, OperationPluginError]: Debug);
+
+/// An event sent by the operations actor to the plugin that owns the current state of an operation
+#[derive(Clone, Debug)]
+pub enum OperationPluginEvent {
+    /// The operation moved to a state owned by the plugin
+    Update(OperationPluginMessage),
+
+    /// On start, an operation has been found in a state owned by the plugin.
+    ///
+    /// The plugin might have been interrupted by the restart while processing this step,
+    /// and has to resume, restart or fail the step.
+    Recover(OperationPluginMessage),
+
+    /// The operation has been cancelled while in a state owned by the plugin.
+    ///
+    /// The plugin has to abort any work in progress for this operation.
+    Cancel(OperationPluginMessage),
+}
+
+/// A request to cancel an operation
+///
+/// Such a request is published, not retained, on the cancel side topic of the operation
+/// `tedge/operations/{subsystem}/{operation}/{request}/{instance}/cancel`,
+/// with an optional JSON payload giving the `reason` of the cancellation.
+#[derive(Clone, Debug)]
+pub struct OperationCancelRequest {
+    pub operation: OperationKey,
+    pub reason: String,
+}
+
+impl OperationCancelRequest {
+    pub const TOPIC_SUFFIX: &'static str = "/cancel";
+}
+
+impl TryFrom<&MqttMessage> for OperationCancelRequest {
+    type Error = String;
+
+    fn try_from(event: &MqttMessage) -> Result<Self, Self::Error> {
+        let topic = event
+            .topic
+            .name
+            .strip_suffix(OperationCancelRequest::TOPIC_SUFFIX)
+            .ok_or_else(|| format!("Not a cancel request topic: {}", event.topic.name))?;
+        let operation = OperationKey::try_from(&topic.to_string())?;
+
+        let reason = event
+            .payload_str()
+            .ok()
+            .and_then(|msg| serde_json::from_str::<Value>(msg).ok())
+            .and_then(|json| {
+                json.get("reason")
+                    .and_then(|v| v.as_str())
+                    .map(str::to_string)
+            })
+            .unwrap_or_else(|| "Cancelled on request".to_string());
+
+        Ok(OperationCancelRequest { operation, reason })
+    }
+}
+
+/// An error raised by an operation plugin while processing a step of an operation,
+/// e.g. a state that can't be decoded or a step task that panicked.
+///
+/// The operation is failed by the operations actor, unless it moved meanwhile to another state.
+#[derive(Clone, Debug)]
+pub struct OperationPluginError {
+    pub operation: OperationKey,
+
+    /// The status of the operation when the error occurred
+    pub status: String,
+
+    pub reason: String,
+}
 
 #[derive(Clone, Debug)]
 pub struct OperationPluginMessage {
@@ -30,10 +104,10 @@ impl OperationPluginMessage {
     pub fn update_from_json(self, json: Value) -> Self {
         let status = json
             .as_object()
-            .map(|o| o.get("status"))
-            .flatten()
-            .map(|v| v.to_string())
-            .unwrap_or("unknown".to_string());
+            .and_then(|o| o.get("status"))
+            .and_then(|v| v.as_str())
+            .unwrap_or("unknown")
+            .to_string();
 
         OperationPluginMessage {
             status,
@@ -88,6 +162,71 @@ impl OperationPluginMessage {
         }
     }
 
+    /// The step counter of this state, if any.
+    ///
+    /// This counter is incremented on each transition published by thin-edge,
+    /// so observers can order the updates of an operation.
+    pub fn step(&self) -> Option<u64> {
+        self.json.get("step").and_then(|v| v.as_u64())
+    }
+
+    pub fn with_step(mut self, step: u64) -> Self {
+        if let Some(o) = self.json.as_object_mut() {
+            o.insert("step".to_string(), step.into());
+        }
+        self
+    }
+
+    /// The participant that published this state, if declared by the payload
+    pub fn publisher(&self) -> Option<&str> {
+        self.json.get("publisher").and_then(|v| v.as_str())
+    }
+
+    pub fn with_publisher(mut self, publisher: &str) -> Self {
+        if let Some(o) = self.json.as_object_mut() {
+            o.insert("publisher".to_string(), publisher.into());
+        }
+        self
+    }
+
+    /// The sha256 digest of the JSON payload, as an hexadecimal string
+    pub fn digest(&self) -> String {
+        Sha256::digest(self.json.to_string().as_bytes())
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect()
+    }
+
+    /// Update this state with the fields of a new one, the new status included
+    pub fn merge(mut self, update: OperationPluginMessage) -> Self {
+        match (self.json.as_object_mut(), update.json.as_object()) {
+            (Some(current), Some(update)) => {
+                for (key, value) in update {
+                    current.insert(key.clone(), value.clone());
+                }
+            }
+            _ => self.json = update.json,
+        }
+
+        OperationPluginMessage {
+            status: update.status,
+            ..self
+        }
+    }
+
+    pub fn cancelled_with(mut self, reason: String) -> Self {
+        let status = "cancelled";
+        if let Some(o) = self.json.as_object_mut() {
+            o.insert("status".to_string(), status.into());
+            o.insert("reason".to_string(), reason.into());
+        }
+
+        OperationPluginMessage {
+            status: status.to_owned(),
+            ..self
+        }
+    }
+
     pub fn failed_with(mut self, reason: String) -> Self {
         let status = "failed";
         self.json.as_object_mut().map(|o| {
@@ -107,7 +246,13 @@ impl TryFrom<&MqttMessage> for OperationPluginMessage {
 
     fn try_from(event: &MqttMessage) -> Result<Self, Self::Error> {
         let operation = OperationKey::try_from(&event.topic)?;
+        OperationPluginMessage::parse(operation, event)
+    }
+}
 
+impl OperationPluginMessage {
+    /// Parse the JSON payload of a message giving a state of an operation
+    fn parse(operation: OperationKey, event: &MqttMessage) -> Result<Self, String> {
         let msg = event
             .payload_str()
             .map_err(|_| "Not an UTF-8 message".to_string())?;
@@ -128,6 +273,56 @@ impl TryFrom<&MqttMessage> for OperationPluginMessage {
     }
 }
 
+/// The side topics used to delegate the steps of an operation to a child device
+///
+/// When an operation on a child device is in a state owned by the `child`:
+/// - this state is forwarded to the child device, retained, on the request side topic
+///   `tedge/operations/{subsystem}/{operation}/{request}/{instance}/child/request`,
+///   so a child device connecting late still gets it,
+/// - the child device publishes its progress on the response side topic
+///   `tedge/operations/{subsystem}/{operation}/{request}/{instance}/child/response`,
+///   declaring its name (the subsystem) as publisher,
+/// - these responses are mirrored on the main topic of the operation,
+/// - the retained request is cleared when the operation reaches a terminal state.
+pub struct ChildDelegation;
+
+impl ChildDelegation {
+    pub const REQUEST_SUFFIX: &'static str = "/child/request";
+    pub const RESPONSE_SUFFIX: &'static str = "/child/response";
+
+    /// The message forwarding to the child device the current state of an operation
+    pub fn request(state: &OperationPluginMessage) -> MqttMessage {
+        let topic = ChildDelegation::request_topic(&state.operation);
+        MqttMessage::new(&topic, state.json.to_string())
+            .with_qos(QoS::AtLeastOnce)
+            .with_retain()
+    }
+
+    /// The message clearing the request retained for the child device
+    pub fn clear(operation: &OperationKey) -> MqttMessage {
+        let topic = ChildDelegation::request_topic(operation);
+        MqttMessage::new(&topic, "")
+            .with_qos(QoS::AtLeastOnce)
+            .with_retain()
+    }
+
+    fn request_topic(operation: &OperationKey) -> Topic {
+        let topic: String = operation.into();
+        Topic::new_unchecked(&format!("{topic}{}", ChildDelegation::REQUEST_SUFFIX))
+    }
+
+    /// The new state of an operation, as published by a child device
+    pub fn response(event: &MqttMessage) -> Result<OperationPluginMessage, String> {
+        let topic = event
+            .topic
+            .name
+            .strip_suffix(ChildDelegation::RESPONSE_SUFFIX)
+            .ok_or_else(|| format!("Not a child response topic: {}", event.topic.name))?;
+        let operation = OperationKey::try_from(&topic.to_string())?;
+        OperationPluginMessage::parse(operation, event)
+    }
+}
+
 impl TryFrom<OperationPluginMessage> for MqttMessage {
     type Error = String;
 
@@ -141,3 +336,42 @@ impl TryFrom<OperationPluginMessage> for MqttMessage {
             .with_retain())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn retain_the_requests_delegated_to_a_child_device() {
+        let topic = Topic::new_unchecked("tedge/operations/child-1/firmware/update/444");
+        let message = MqttMessage::new(&topic, r#"{"status":"executing"}"#);
+        let state = OperationPluginMessage::try_from(&message).unwrap();
+
+        let request = ChildDelegation::request(&state);
+        assert_eq!(
+            request.topic.name,
+            "tedge/operations/child-1/firmware/update/444/child/request"
+        );
+        assert!(request.retain);
+
+        let clear = ChildDelegation::clear(&state.operation);
+        assert_eq!(clear.topic, request.topic);
+        assert!(clear.retain);
+        assert!(clear.payload_bytes().is_empty());
+    }
+
+    #[test]
+    fn parse_the_responses_of_a_child_device() {
+        let topic =
+            Topic::new_unchecked("tedge/operations/child-1/firmware/update/444/child/response");
+        let message = MqttMessage::new(&topic, r#"{"status":"successful","publisher":"child-1"}"#);
+        let response = ChildDelegation::response(&message).unwrap();
+        assert_eq!(response.operation.subsystem, "child-1");
+        assert_eq!(response.status, "successful");
+        assert_eq!(response.publisher(), Some("child-1"));
+
+        let topic = Topic::new_unchecked("tedge/operations/child-1/firmware/update/444");
+        let message = MqttMessage::new(&topic, r#"{"status":"successful"}"#);
+        assert!(ChildDelegation::response(&message).is_err());
+    }
+}
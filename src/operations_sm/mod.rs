@@ -1,4 +1,8 @@
 pub mod actor;
 pub mod builder;
 pub mod config;
+pub mod journal;
 pub mod messages;
+pub mod owners;
+pub mod plugin;
+pub mod script;
@@ -0,0 +1,360 @@
+use crate::operations_sm::config::{OperationFilter, OperationWorkflow};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::BTreeMap;
+use tedge_mqtt_ext::{MqttMessage, QoS, Topic, TopicFilter};
+
+/// The registration of an out-of-process plugin as the owner of some operation states
+///
+/// Such a registration is published, retained, on `tedge/operations/owners/{name}`:
+///
+/// ```json
+/// { "operation": "configuration", "request": "update", "states": ["init"], "heartbeat": 60 }
+/// ```
+///
+/// The owner is then expected to publish a heartbeat on `tedge/operations/owners/{name}/heartbeat`
+/// at the given interval, in seconds. An empty retained message unregisters the owner,
+/// and can be set as the last will of the plugin.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct OwnerRegistration {
+    /// The operations handled by this owner
+    #[serde(flatten)]
+    pub filter: OperationFilter,
+
+    /// The states owned by this owner
+    pub states: Vec<String>,
+
+    /// The heartbeat interval, in seconds
+    #[serde(default = "default_heartbeat")]
+    pub heartbeat: u64,
+}
+
+fn default_heartbeat() -> u64 {
+    60
+}
+
+impl OwnerRegistration {
+    pub const TOPIC_PREFIX: &'static str = "tedge/operations/owners/";
+    pub const HEARTBEAT_SUFFIX: &'static str = "/heartbeat";
+
+    /// The owner names used by the workflows for other participants than the registered owners
+    pub const RESERVED_NAMES: [&'static str; 3] = ["tedge", "child", "external"];
+
+    /// Check a registration against the workflows
+    ///
+    /// The owner name must not be reserved, the heartbeat interval must be positive
+    /// and each registered state must be owned by this owner or by `external` in a workflow of the operation.
+    pub fn check<'a>(
+        &self,
+        name: &str,
+        workflows: impl Iterator<Item = &'a OperationWorkflow>,
+    ) -> Result<(), String> {
+        if OwnerRegistration::RESERVED_NAMES.contains(&name) {
+            return Err(format!("{name} is a reserved owner name"));
+        }
+        if self.heartbeat == 0 {
+            return Err("The heartbeat interval must be positive".to_string());
+        }
+
+        let workflows: Vec<_> = workflows
+            .filter(|workflow| workflow.filter.overlaps(&self.filter))
+            .collect();
+        let not_owned: Vec<&String> = self
+            .states
+            .iter()
+            .filter(|status| {
+                !workflows.iter().any(|workflow| {
+                    workflow
+                        .states
+                        .get(status.as_str())
+                        .map(|state| state.owner == name || state.owner == "external")
+                        .unwrap_or(false)
+                })
+            })
+            .collect();
+        if !not_owned.is_empty() {
+            return Err(format!(
+                "The states {not_owned:?} are owned neither by {name} nor by external in the workflows"
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// A message published by an out-of-process plugin
+pub enum OwnerMessage {
+    Register(String, OwnerRegistration),
+    Unregister(String),
+    Heartbeat(String),
+}
+
+impl TryFrom<&MqttMessage> for OwnerMessage {
+    type Error = String;
+
+    fn try_from(message: &MqttMessage) -> Result<Self, Self::Error> {
+        let topic = &message.topic.name;
+        let path = topic
+            .strip_prefix(OwnerRegistration::TOPIC_PREFIX)
+            .ok_or_else(|| format!("Not an owner topic: {topic}"))?;
+        if let Some(name) = path.strip_suffix(OwnerRegistration::HEARTBEAT_SUFFIX) {
+            return Ok(OwnerMessage::Heartbeat(name.to_string()));
+        }
+
+        let name = path.to_string();
+        if message.payload_bytes().is_empty() {
+            return Ok(OwnerMessage::Unregister(name));
+        }
+        let registration = message
+            .payload_str()
+            .map_err(|err| format!("Invalid registration of {name}: {err}"))
+            .and_then(|payload| {
+                serde_json::from_str(payload)
+                    .map_err(|err| format!("Invalid registration of {name}: {err}"))
+            })?;
+        Ok(OwnerMessage::Register(name, registration))
+    }
+}
+
+/// An out-of-process plugin, as known by the operations actor
+pub struct ExternalOwner {
+    pub registration: OwnerRegistration,
+    topics: TopicFilter,
+
+    /// False when no heartbeat has been received in time
+    pub alive: bool,
+
+    /// The number of heartbeats received, used to detect the expired heartbeat timers
+    beats: u64,
+}
+
+/// The out-of-process plugins, indexed by name
+#[derive(Default)]
+pub struct ExternalOwners {
+    owners: BTreeMap<String, ExternalOwner>,
+
+    /// The registrations that have been rejected, with the reason
+    rejected: BTreeMap<String, String>,
+}
+
+impl ExternalOwners {
+    /// Register a new owner, or update its registration, once checked against the workflows.
+    ///
+    /// Return the number of heartbeats, to be given back when the heartbeat timer expires.
+    /// A rejected registration is listed with its error by the capability report.
+    pub fn register<'a>(
+        &mut self,
+        name: &str,
+        registration: OwnerRegistration,
+        workflows: impl Iterator<Item = &'a OperationWorkflow>,
+    ) -> Result<u64, String> {
+        let checked = registration
+            .check(name, workflows)
+            .and_then(|()| (&registration.filter).try_into());
+        let topics = match checked {
+            Ok(topics) => topics,
+            Err(err) => {
+                self.rejected.insert(name.to_string(), err.clone());
+                return Err(err);
+            }
+        };
+        self.rejected.remove(name);
+        let beats = self.owners.get(name).map(|o| o.beats + 1).unwrap_or(1);
+        self.owners.insert(
+            name.to_string(),
+            ExternalOwner {
+                registration,
+                topics,
+                alive: true,
+                beats,
+            },
+        );
+        Ok(beats)
+    }
+
+    pub fn unregister(&mut self, name: &str) -> Option<ExternalOwner> {
+        self.rejected.remove(name);
+        self.owners.remove(name)
+    }
+
+    /// Remove the previous registration of an owner whose new registration has been rejected
+    pub fn remove(&mut self, name: &str) {
+        self.owners.remove(name);
+    }
+
+    pub fn is_registered(&self, name: &str) -> bool {
+        self.owners.contains_key(name)
+    }
+
+    /// Record a heartbeat, returning the number of heartbeats and the heartbeat interval,
+    /// or `None` if the owner is not registered.
+    pub fn heartbeat(&mut self, name: &str) -> Option<(u64, u64)> {
+        let owner = self.owners.get_mut(name)?;
+        owner.alive = true;
+        owner.beats += 1;
+        Some((owner.beats, owner.registration.heartbeat))
+    }
+
+    /// Check if the owner is alive, given a heartbeat timer that expired.
+    ///
+    /// Return true if the owner is no longer alive, i.e. if no heartbeat has been received since.
+    pub fn expire(&mut self, name: &str, beats: u64) -> bool {
+        match self.owners.get_mut(name) {
+            Some(owner) if owner.beats == beats && owner.alive => {
+                owner.alive = false;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Declare an owner lost, e.g. when unregistered
+    pub fn expire_now(&mut self, name: &str) {
+        if let Some(owner) = self.owners.get_mut(name) {
+            owner.alive = false;
+        }
+    }
+
+    pub fn is_alive(&self, name: &str) -> bool {
+        self.owners.get(name).map(|o| o.alive).unwrap_or(false)
+    }
+
+    /// The registered owners of an operation state
+    ///
+    /// The owner given by the workflow is either the name of the owner or `external`.
+    pub fn owners_of<'a>(
+        &'a self,
+        topic: &'a Topic,
+        status: &'a str,
+        owner: &'a str,
+    ) -> impl Iterator<Item = (&'a String, &'a ExternalOwner)> {
+        self.owners.iter().filter(move |(name, o)| {
+            (owner == "external" || owner == name.as_str())
+                && o.topics.accept_topic(topic)
+                && o.registration.states.iter().any(|s| s == status)
+        })
+    }
+
+    /// The capability report, published retained on `tedge/operations/capabilities`,
+    /// listing the workflows, the external owners and the rejected registrations.
+    pub fn capability_report<'a>(
+        &self,
+        workflows: impl Iterator<Item = &'a OperationWorkflow>,
+    ) -> MqttMessage {
+        let workflows: Vec<_> = workflows.collect();
+        let owners: Vec<_> = self
+            .owners
+            .iter()
+            .map(|(name, owner)| {
+                let mut json = serde_json::to_value(&owner.registration).unwrap_or_default();
+                if let Some(o) = json.as_object_mut() {
+                    o.insert("name".to_string(), name.clone().into());
+                    o.insert("alive".to_string(), owner.alive.into());
+                }
+                json
+            })
+            .collect();
+        let rejected: Vec<_> = self
+            .rejected
+            .iter()
+            .map(|(name, error)| json!({ "name": name, "error": error }))
+            .collect();
+        let report = json!({
+            "workflows": workflows,
+            "owners": owners,
+            "rejected": rejected,
+        });
+        let topic = Topic::new_unchecked("tedge/operations/capabilities");
+        MqttMessage::new(&topic, report.to_string())
+            .with_qos(QoS::AtLeastOnce)
+            .with_retain()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn workflows() -> Vec<OperationWorkflow> {
+        let workflow = toml::from_str(
+            r#"
+            operation = "configuration"
+            request = "update"
+            [init]
+            owner = "external"
+            next = ["scheduled"]
+            [scheduled]
+            owner = "scheduler"
+            next = ["successful"]
+            [successful]
+            next = []
+            "#,
+        )
+        .unwrap();
+        vec![workflow]
+    }
+
+    fn registration(states: &[&str], heartbeat: u64) -> OwnerRegistration {
+        OwnerRegistration {
+            filter: OperationFilter {
+                operation: Some("configuration".to_string()),
+                ..Default::default()
+            },
+            states: states.iter().map(|s| s.to_string()).collect(),
+            heartbeat,
+        }
+    }
+
+    #[test]
+    fn register_the_owner_of_external_or_named_states() {
+        let mut owners = ExternalOwners::default();
+        let registration = registration(&["init", "scheduled"], 60);
+
+        assert!(owners
+            .register("scheduler", registration, workflows().iter())
+            .is_ok());
+        assert!(owners.is_alive("scheduler"));
+    }
+
+    #[test]
+    fn reject_invalid_registrations() {
+        let mut owners = ExternalOwners::default();
+        for name in OwnerRegistration::RESERVED_NAMES {
+            assert!(owners
+                .register(name, registration(&["init"], 60), workflows().iter())
+                .is_err());
+        }
+        assert!(owners
+            .register("scheduler", registration(&["init"], 0), workflows().iter())
+            .is_err());
+        assert!(owners
+            .register(
+                "other",
+                registration(&["scheduled"], 60),
+                workflows().iter()
+            )
+            .is_err());
+        assert!(owners
+            .register("other", registration(&["unknown"], 60), workflows().iter())
+            .is_err());
+        assert!(!owners.is_registered("other"));
+    }
+
+    #[test]
+    fn report_the_rejected_registrations() {
+        let mut owners = ExternalOwners::default();
+        let _ = owners.register("tedge", registration(&["init"], 60), workflows().iter());
+
+        let report = owners.capability_report(workflows().iter());
+        let report: serde_json::Value = serde_json::from_slice(report.payload_bytes()).unwrap();
+        assert_eq!(report["rejected"][0]["name"], "tedge");
+        assert_eq!(
+            report["rejected"][0]["error"],
+            "tedge is a reserved owner name"
+        );
+
+        owners.unregister("tedge");
+        let report = owners.capability_report(workflows().iter());
+        let report: serde_json::Value = serde_json::from_slice(report.payload_bytes()).unwrap();
+        assert_eq!(report["rejected"], json!([]));
+    }
+}
@@ -0,0 +1,907 @@
+use crate::operations_sm::builder::OperationsActorBuilder;
+use crate::operations_sm::config::{OperationKey, OperationWorkflow};
+use crate::operations_sm::messages::{
+    OperationPluginError, OperationPluginEvent, OperationPluginMessage,
+};
+use async_trait::async_trait;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::any::Any;
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::fmt::Debug;
+use std::future::Future;
+use std::panic::AssertUnwindSafe;
+use std::path::Path;
+use tedge_actors::futures::future::{AbortHandle, Abortable, Aborted};
+use tedge_actors::futures::stream::FuturesUnordered;
+use tedge_actors::futures::{FutureExt, StreamExt};
+use tedge_actors::{
+    Actor, Builder, DynSender, MessageReceiver, RuntimeError, RuntimeRequest, RuntimeRequestSink,
+    Sender, ServiceConsumer, SimpleMessageBox, SimpleMessageBoxBuilder,
+};
+use tokio::task::JoinHandle;
+
+/// The states of an operation, as derived by `#[derive(OperationState)]`
+pub trait OperationStates {
+    /// The status of all the states
+    const STATES: &'static [&'static str];
+
+    /// The default workflow of the operation
+    fn workflow() -> OperationWorkflow;
+}
+
+/// The configuration of an operation plugin, read from a TOML file
+pub trait PluginConfig: DeserializeOwned + Default {
+    /// Read the plugin configuration from a TOML file,
+    /// returning the default configuration if there is no such file.
+    fn from_file(path: impl AsRef<Path>) -> Result<Self, anyhow::Error> {
+        let path = path.as_ref();
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let config = std::fs::read_to_string(path)?;
+        Ok(toml::from_str(&config)?)
+    }
+}
+
+/// An operation plugin implements the steps owned by thin-edge of an operation workflow.
+///
+/// The plugin only has to implement the step handlers over typed states,
+/// the default workflow being derived from the state enum,
+/// the conversions from and to the operation messages being derived from the serde definitions:
+/// - the states are the variants of an enum, tagged by the `status` field of the JSON payload,
+/// - the request is the part of the payload that is common to all the states,
+/// - the fields unknown to the states, e.g. added by a custom script, are given back with the next state.
+///
+/// ```ignore
+/// #[derive(Clone, Debug, Serialize, Deserialize, OperationState)]
+/// #[serde(tag = "status", rename_all = "kebab-case")]
+/// #[operation(operation = "configuration", request = "update")]
+/// pub enum ConfigUpdateState {
+///     #[operation(next = ["scheduled", "failed", "cancelled"])]
+///     Init {
+///         #[serde(flatten)]
+///         request: ConfigUpdateRequest,
+///     },
+///     #[operation(next = [])]
+///     Failed {
+///         #[serde(flatten)]
+///         request: ConfigUpdateRequest,
+///         reason: String,
+///     },
+///     ...
+/// }
+/// ```
+///
+/// Most plugins move a new request from its `init` state to a next step with no other action than a sanity check.
+/// Having an init state with an automatic transition to an other step is done in order to:
+/// - let the users plug their own behavior to check, prepare or adapt the request,
+/// - while keeping unchanged the sub-systems that create these requests (i.e. the mappers).
+pub trait OperationPlugin: Send + Sync + 'static {
+    /// The states of an operation, as handled by this plugin
+    type State: OperationStates + Serialize + DeserializeOwned + Clone + Debug + Send + 'static;
+
+    /// The states processed by this plugin, i.e. the steps it implements.
+    ///
+    /// All the non-terminal states of the default workflow that are owned by tedge without a script
+    /// have to be listed, which is checked on start. The other states are only given to `release`.
+    const HANDLED_STATES: &'static [&'static str];
+
+    /// The name of the actor running this plugin
+    fn name(&self) -> &str;
+
+    /// The default workflow of the operations handled by this plugin
+    fn workflow(&self) -> OperationWorkflow {
+        Self::State::workflow()
+    }
+
+    /// The default workflows of the operations handled by this plugin,
+    /// e.g. to delegate some steps to the child devices.
+    ///
+    /// For a given operation, the first workflow whose filter matches applies.
+    /// By default, the default workflow applies to all the subsystems.
+    fn workflows(&self) -> Vec<OperationWorkflow> {
+        vec![self.workflow()]
+    }
+
+    /// Called once when the actor running this plugin starts,
+    /// e.g. to launch the services used by the plugin.
+    fn start(&mut self) {}
+
+    /// Process a new state of an operation, one of the `HANDLED_STATES`,
+    /// returning the next state if immediately known.
+    ///
+    /// The long-running steps have to be spawned as background tasks.
+    /// A panic, either in this handler or in a background task, fails the operation.
+    fn update(
+        &mut self,
+        operation: &OperationKey,
+        state: Self::State,
+        tasks: &mut OperationPluginTasks<Self::State>,
+    ) -> Option<Self::State>;
+
+    /// Resume an operation found on start in a state owned by this plugin.
+    ///
+    /// By default, the step is simply processed again.
+    fn recover(
+        &mut self,
+        operation: &OperationKey,
+        state: Self::State,
+        tasks: &mut OperationPluginTasks<Self::State>,
+    ) -> Option<Self::State> {
+        self.update(operation, state, tasks)
+    }
+
+    /// Notified of a state not handled by this plugin, e.g. to release the resources of a completed operation.
+    ///
+    /// By default, nothing is done: these states are only useful for the other participants.
+    fn release(&mut self, _operation: &OperationKey, _state: Self::State) {}
+
+    /// Abort the work in progress for a cancelled operation.
+    ///
+    /// By default, the background task of the operation, if any, is aborted.
+    fn cancel(
+        &mut self,
+        operation: &OperationKey,
+        _state: Self::State,
+        tasks: &mut OperationPluginTasks<Self::State>,
+    ) -> Option<Self::State> {
+        tasks.abort(operation);
+        None
+    }
+}
+
+/// Convert a typed state into an operation message
+pub fn state_to_message<State: Serialize>(
+    operation: OperationKey,
+    state: &State,
+) -> Result<OperationPluginMessage, String> {
+    let json = serde_json::to_value(state).map_err(|err| format!("Invalid state: {err}"))?;
+    let status = json
+        .get("status")
+        .and_then(|v| v.as_str())
+        .ok_or("Invalid state: missing status")?
+        .to_string();
+    Ok(OperationPluginMessage {
+        operation,
+        status,
+        json,
+    })
+}
+
+/// Convert an operation message into a typed state
+pub fn message_to_state<State: DeserializeOwned>(
+    message: &OperationPluginMessage,
+) -> Result<State, String> {
+    serde_json::from_value(message.json.clone())
+        .map_err(|err| format!("Invalid {} state: {err}", message.status))
+}
+
+/// The fields of an operation message that are unknown to its typed state,
+/// e.g. the fields added by a custom script or by a mapper.
+pub fn unknown_fields<State: Serialize>(
+    message: &OperationPluginMessage,
+    state: &State,
+) -> Map<String, Value> {
+    let known = serde_json::to_value(state).unwrap_or_default();
+    let known = known.as_object();
+    message
+        .json
+        .as_object()
+        .into_iter()
+        .flatten()
+        .filter(|(key, _)| !known.is_some_and(|known| known.contains_key(*key)))
+        .map(|(key, value)| (key.clone(), value.clone()))
+        .collect()
+}
+
+/// Add to an operation message the fields unknown to the typed states,
+/// the fields of the message taking precedence.
+pub fn with_unknown_fields(
+    mut message: OperationPluginMessage,
+    fields: &Map<String, Value>,
+) -> OperationPluginMessage {
+    if let Some(json) = message.json.as_object_mut() {
+        for (key, value) in fields {
+            json.entry(key.clone()).or_insert_with(|| value.clone());
+        }
+    }
+    message
+}
+
+/// A background task, returning the new state of an operation unless aborted
+type PluginTask<State> = JoinHandle<Result<TaskOutcome<State>, Aborted>>;
+
+/// The outcome of a background task: the operation, the task id,
+/// and either the new state of the operation or the reason why the task panicked.
+type TaskOutcome<State> = (OperationKey, u64, Result<Option<State>, String>);
+
+/// A task in progress for an operation
+struct PendingTask {
+    /// Identify the task among those spawned for the same operation
+    id: u64,
+
+    /// The status of the operation once the step that spawned the task completed,
+    /// against which a failure of the task is reported
+    status: String,
+
+    abort_handle: AbortHandle,
+}
+
+/// The background tasks of an operation plugin, at most one per operation.
+///
+/// The outcome of a task, if any, is the new state of the operation.
+pub struct OperationPluginTasks<State> {
+    tasks: FuturesUnordered<PluginTask<State>>,
+
+    /// The tasks in progress, indexed by operation topic
+    pending: HashMap<String, PendingTask>,
+
+    /// The status of the step being processed, i.e. of the step of the spawned tasks
+    status: String,
+
+    /// The id of the next spawned task
+    next_id: u64,
+}
+
+impl<State: Send + 'static> OperationPluginTasks<State> {
+    pub fn new() -> Self {
+        OperationPluginTasks {
+            tasks: FuturesUnordered::new(),
+            pending: HashMap::new(),
+            status: String::new(),
+            next_id: 0,
+        }
+    }
+
+    /// Run in the background a step of an operation,
+    /// aborting the task previously spawned for this operation, if still in progress.
+    pub fn spawn(
+        &mut self,
+        operation: &OperationKey,
+        task: impl Future<Output = Option<State>> + Send + 'static,
+    ) {
+        let id = self.next_id;
+        self.next_id += 1;
+        let (abort_handle, abort_registration) = AbortHandle::new_pair();
+        let pending = PendingTask {
+            id,
+            status: self.status.clone(),
+            abort_handle,
+        };
+        if let Some(previous) = self.pending.insert(operation.into(), pending) {
+            previous.abort_handle.abort();
+        }
+        let operation = operation.clone();
+        let task = async move {
+            let outcome = AssertUnwindSafe(task)
+                .catch_unwind()
+                .await
+                .map_err(|panic| format!("A plugin task panicked: {}", panic_reason(panic)));
+            (operation, id, outcome)
+        };
+        self.tasks
+            .push(tokio::spawn(Abortable::new(task, abort_registration)));
+    }
+
+    /// Tag the task spawned for an operation since the given task id, if any,
+    /// with the status of the state in which the step leaves the operation.
+    fn leave_in(&mut self, operation: &OperationKey, first_task: u64, status: String) {
+        let topic: String = operation.into();
+        if let Some(task) = self.pending.get_mut(&topic) {
+            if task.id >= first_task {
+                task.status = status;
+            }
+        }
+    }
+
+    /// Check if a task is in progress for an operation
+    pub fn is_pending(&self, operation: &OperationKey) -> bool {
+        let topic: String = operation.into();
+        self.pending.contains_key(&topic)
+    }
+
+    /// Abort the task in progress for an operation, returning true if there was one
+    pub fn abort(&mut self, operation: &OperationKey) -> bool {
+        let topic: String = operation.into();
+        match self.pending.remove(&topic) {
+            Some(task) => {
+                task.abort_handle.abort();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Wait for the next outcome of a background task,
+    /// returning the operation, its status once the task spawned and the outcome.
+    ///
+    /// The outcome of a task that has been superseded by another task for the same operation is discarded.
+    pub(crate) async fn next(
+        &mut self,
+    ) -> Option<(OperationKey, String, Result<Option<State>, String>)> {
+        loop {
+            match self.tasks.next().await? {
+                Ok(Ok((operation, id, outcome))) => {
+                    let topic: String = (&operation).into();
+                    if self.pending.get(&topic).is_some_and(|task| task.id == id) {
+                        let status = self
+                            .pending
+                            .remove(&topic)
+                            .map(|task| task.status)
+                            .unwrap_or_default();
+                        return Some((operation, status, outcome));
+                    }
+                    log::debug!("Discard the outcome of a superseded task for {topic}");
+                }
+                Ok(Err(Aborted)) => {}
+                Err(err) => log::error!("Fail to run an operation plugin task: {err}"),
+            }
+        }
+    }
+}
+
+impl<State: Send + 'static> Default for OperationPluginTasks<State> {
+    fn default() -> Self {
+        OperationPluginTasks::new()
+    }
+}
+
+/// The reason of a panic, as given by its payload
+fn panic_reason(panic: Box<dyn Any + Send>) -> String {
+    if let Some(reason) = panic.downcast_ref::<&str>() {
+        reason.to_string()
+    } else if let Some(reason) = panic.downcast_ref::<String>() {
+        reason.clone()
+    } else {
+        "unknown reason".to_string()
+    }
+}
+
+/// What has to be sent to the operations actor after a step of an operation
+enum StepOutcome<State> {
+    NewState(OperationKey, State),
+    Error(OperationPluginError),
+}
+
+/// The actor running an operation plugin
+///
+/// The errors of the plugin are sent to the operations actor to fail the operations.
+pub struct OperationPluginActor<P: OperationPlugin> {
+    plugin: P,
+    message_box: SimpleMessageBox<OperationPluginEvent, OperationPluginMessage>,
+    error_sender: DynSender<OperationPluginError>,
+    tasks: OperationPluginTasks<P::State>,
+
+    /// The fields of the current state of each operation that are unknown to the typed states,
+    /// and that are given back with the next state.
+    unknown_fields: HashMap<String, Map<String, Value>>,
+}
+
+#[async_trait]
+impl<P: OperationPlugin> Actor for OperationPluginActor<P> {
+    fn name(&self) -> &str {
+        self.plugin.name()
+    }
+
+    async fn run(&mut self) -> Result<(), RuntimeError> {
+        self.plugin.start();
+        loop {
+            let maybe_response = tokio::select! {
+                Some(event) = self.message_box.recv() => {
+                    self.process(event)
+                }
+                Some((operation, status, outcome)) = self.tasks.next() => {
+                    match outcome {
+                        Ok(state) => state.map(|state| StepOutcome::NewState(operation, state)),
+                        Err(reason) => Some(StepOutcome::Error(OperationPluginError { operation, status, reason })),
+                    }
+                }
+                else => {
+                    return Ok(());
+                }
+            };
+
+            match maybe_response {
+                Some(StepOutcome::NewState(operation, state)) => {
+                    let status = state_status(&state);
+                    match state_to_message(operation.clone(), &state) {
+                        Ok(message) => {
+                            let topic: String = (&operation).into();
+                            let message = match self.unknown_fields.get(&topic) {
+                                Some(fields) => with_unknown_fields(message, fields),
+                                None => message,
+                            };
+                            self.message_box.send(message).await?
+                        }
+                        Err(reason) => {
+                            let error = OperationPluginError {
+                                operation,
+                                status,
+                                reason,
+                            };
+                            self.error_sender.send(error).await?
+                        }
+                    }
+                }
+                Some(StepOutcome::Error(error)) => self.error_sender.send(error).await?,
+                None => {}
+            }
+        }
+    }
+}
+
+impl<P: OperationPlugin> OperationPluginActor<P> {
+    /// Process an event with the plugin, returning the new state of the operation if any,
+    /// or the error that has to fail the operation.
+    fn process(&mut self, event: OperationPluginEvent) -> Option<StepOutcome<P::State>> {
+        let (OperationPluginEvent::Update(message)
+        | OperationPluginEvent::Recover(message)
+        | OperationPluginEvent::Cancel(message)) = &event;
+        let operation = message.operation.clone();
+        let status = message.status.clone();
+
+        let state = match message_to_state(message) {
+            Ok(state) => state,
+            Err(reason) => {
+                if matches!(event, OperationPluginEvent::Cancel(_)) {
+                    let topic: String = (&operation).into();
+                    log::error!("Can not cancel {topic}: {reason}");
+                    return None;
+                }
+                return Some(StepOutcome::Error(OperationPluginError {
+                    operation,
+                    status,
+                    reason,
+                }));
+            }
+        };
+
+        let topic: String = (&operation).into();
+        let handled = P::HANDLED_STATES.contains(&status.as_str());
+        if handled {
+            let fields = unknown_fields(message, &state);
+            self.unknown_fields.insert(topic, fields);
+        } else {
+            self.unknown_fields.remove(&topic);
+        }
+
+        match run_step(&mut self.plugin, &mut self.tasks, &event, state) {
+            Ok(new_state) => new_state.map(|state| StepOutcome::NewState(operation, state)),
+            Err(reason) => Some(StepOutcome::Error(OperationPluginError {
+                operation,
+                status,
+                reason,
+            })),
+        }
+    }
+}
+
+/// Process with the plugin the decoded state of an event,
+/// returning the new state of the operation if any, or the reason why the plugin panicked.
+///
+/// The tasks spawned by this step are tagged with the state in which the step leaves the operation,
+/// i.e. the new state if any: the failure of such a task fails the operation in this new state.
+fn run_step<P: OperationPlugin>(
+    plugin: &mut P,
+    tasks: &mut OperationPluginTasks<P::State>,
+    event: &OperationPluginEvent,
+    state: P::State,
+) -> Result<Option<P::State>, String> {
+    let (OperationPluginEvent::Update(message)
+    | OperationPluginEvent::Recover(message)
+    | OperationPluginEvent::Cancel(message)) = event;
+    let operation = &message.operation;
+    let handled = P::HANDLED_STATES.contains(&message.status.as_str());
+
+    tasks.status = message.status.clone();
+    let first_task = tasks.next_id;
+    let new_state = std::panic::catch_unwind(AssertUnwindSafe(|| match event {
+        OperationPluginEvent::Cancel(_) => plugin.cancel(operation, state, &mut *tasks),
+        _ if !handled => {
+            plugin.release(operation, state);
+            None
+        }
+        OperationPluginEvent::Update(_) => plugin.update(operation, state, &mut *tasks),
+        OperationPluginEvent::Recover(_) => plugin.recover(operation, state, &mut *tasks),
+    }))
+    .map_err(|panic| format!("{} panicked: {}", plugin.name(), panic_reason(panic)))?;
+
+    if let Some(new_state) = &new_state {
+        tasks.leave_in(operation, first_task, state_status(new_state));
+    }
+    Ok(new_state)
+}
+
+/// The status of a typed state, as given by its serialization
+fn state_status<State: Serialize>(state: &State) -> String {
+    serde_json::to_value(state)
+        .ok()
+        .and_then(|json| {
+            json.get("status")
+                .and_then(|v| v.as_str())
+                .map(str::to_string)
+        })
+        .unwrap_or_default()
+}
+
+/// The non-terminal states of a workflow that are owned by tedge without a script,
+/// but not in the given list of handled states
+pub fn unhandled_states<'a>(workflow: &'a OperationWorkflow, handled: &[&str]) -> Vec<&'a str> {
+    let mut unhandled: Vec<&str> = workflow
+        .states
+        .iter()
+        .filter(|(status, state)| {
+            state.owner == "tedge"
+                && state.script.is_none()
+                && !state.next.is_empty()
+                && !handled.contains(&status.as_str())
+        })
+        .map(|(status, _)| status.as_str())
+        .collect();
+    unhandled.sort();
+    unhandled
+}
+
+/// Build the actor running an operation plugin, connected to the operations actor
+pub struct OperationPluginBuilder<P: OperationPlugin> {
+    plugin: P,
+    message_box: SimpleMessageBoxBuilder<OperationPluginEvent, OperationPluginMessage>,
+    error_sender: DynSender<OperationPluginError>,
+}
+
+impl<P: OperationPlugin> OperationPluginBuilder<P> {
+    pub fn new(plugin: P, operations: &mut OperationsActorBuilder) -> Self {
+        let workflows = plugin.workflows();
+        for workflow in workflows.iter() {
+            for status in unhandled_states(workflow, P::HANDLED_STATES) {
+                log::error!(
+                    "{}: the state {status} is owned by tedge, but is not handled by the plugin",
+                    plugin.name()
+                );
+            }
+            operations.register_plugin_states(workflow.filter.clone(), P::HANDLED_STATES);
+        }
+        for status in P::HANDLED_STATES {
+            if !P::State::STATES.contains(status) {
+                log::error!(
+                    "{}: the state {status} is declared as handled, but is not a state of the operation",
+                    plugin.name()
+                );
+            }
+        }
+
+        let message_box = SimpleMessageBoxBuilder::new(plugin.name(), 16);
+        let error_sender = operations.get_error_sender();
+        let mut builder = OperationPluginBuilder {
+            plugin,
+            message_box,
+            error_sender,
+        };
+        builder.set_connection(operations);
+        for workflow in workflows.into_iter().skip(1) {
+            operations.register_operation_plugin(builder.get_response_sender(), workflow);
+        }
+        builder
+    }
+}
+
+impl<P: OperationPlugin>
+    ServiceConsumer<OperationPluginMessage, OperationPluginEvent, OperationWorkflow>
+    for OperationPluginBuilder<P>
+{
+    /// The first workflow of the plugin, the other ones being registered along
+    fn get_config(&self) -> OperationWorkflow {
+        self.plugin.workflows().swap_remove(0)
+    }
+
+    fn set_request_sender(&mut self, request_sender: DynSender<OperationPluginMessage>) {
+        self.message_box.set_request_sender(request_sender)
+    }
+
+    fn get_response_sender(&self) -> DynSender<OperationPluginEvent> {
+        self.message_box.get_response_sender()
+    }
+}
+
+impl<P: OperationPlugin> Builder<OperationPluginActor<P>> for OperationPluginBuilder<P> {
+    type Error = Infallible;
+
+    fn try_build(self) -> Result<OperationPluginActor<P>, Self::Error> {
+        Ok(OperationPluginActor {
+            plugin: self.plugin,
+            message_box: self.message_box.build(),
+            error_sender: self.error_sender,
+            tasks: OperationPluginTasks::new(),
+            unknown_fields: HashMap::new(),
+        })
+    }
+}
+
+impl<P: OperationPlugin> RuntimeRequestSink for OperationPluginBuilder<P> {
+    fn get_signal_sender(&self) -> DynSender<RuntimeRequest> {
+        self.message_box.get_signal_sender()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command::actor::CommandManager;
+    use crate::configuration::actor::ConfigManager;
+    use crate::configuration::list::actor::ConfigListManager;
+    use crate::configuration::snapshot::actor::ConfigSnapshotManager;
+    use crate::device::actor::DeviceManager;
+    use crate::firmware::actor::FirmwareManager;
+    use crate::logs::actor::LogManager;
+    use crate::software::actor::SoftwareManager;
+    use crate::software::list::actor::SoftwareListManager;
+    use serde::Deserialize;
+    use tedge_mqtt_state_machine_macros::OperationState;
+
+    #[test]
+    fn list_the_unhandled_states() {
+        let workflow: OperationWorkflow = toml::from_str(
+            r#"
+            operation = "configuration"
+            [init]
+            owner = "external"
+            next = ["scheduled"]
+            [scheduled]
+            next = ["checked"]
+            [checked]
+            script = "check.sh"
+            next = ["installing"]
+            [installing]
+            next = ["successful"]
+            [successful]
+            next = []
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            unhandled_states(&workflow, &["scheduled"]),
+            vec!["installing"]
+        );
+        assert!(unhandled_states(&workflow, &["scheduled", "installing"]).is_empty());
+    }
+
+    /// Check that a plugin handles all the steps of its default workflow
+    fn assert_all_steps_handled<P: OperationPlugin>() {
+        let workflow = P::State::workflow();
+        assert!(unhandled_states(&workflow, P::HANDLED_STATES).is_empty());
+        assert!(P::HANDLED_STATES
+            .iter()
+            .all(|status| P::State::STATES.contains(status)));
+    }
+
+    #[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+    struct DemoRequest {
+        target: String,
+    }
+
+    #[derive(Clone, Debug, PartialEq, Serialize, Deserialize, OperationState)]
+    #[serde(tag = "status", rename_all = "kebab-case")]
+    #[operation(operation = "demo", request = "update")]
+    enum DemoState {
+        #[operation(next = ["rolled-back", "done"])]
+        Init {
+            #[serde(flatten)]
+            request: DemoRequest,
+        },
+        #[operation(owner = "external", next = ["done"])]
+        RolledBack {
+            #[serde(flatten)]
+            request: DemoRequest,
+            reason: String,
+        },
+        #[serde(rename = "done")]
+        #[operation(next = [])]
+        Successful {
+            #[serde(flatten)]
+            request: DemoRequest,
+        },
+    }
+
+    #[test]
+    fn derive_the_operation_states() {
+        let request = DemoRequest {
+            target: "demo".to_string(),
+        };
+        let state = DemoState::RolledBack {
+            request: request.clone(),
+            reason: "oops".to_string(),
+        };
+
+        assert_eq!(DemoState::STATES, &["init", "rolled-back", "done"]);
+        assert_eq!(state.status(), "rolled-back");
+        assert_eq!(state.status(), state_status(&state));
+        assert_eq!(state.request(), &request);
+        assert_eq!(state.reason(), Some(&"oops".to_string()));
+        assert_eq!(DemoState::Successful { request }.reason(), None);
+    }
+
+    #[test]
+    fn derive_the_default_workflow() {
+        let workflow = DemoState::workflow();
+
+        assert_eq!(workflow.filter.operation.as_deref(), Some("demo"));
+        assert_eq!(workflow.filter.request.as_deref(), Some("update"));
+        assert_eq!(workflow.states["init"].owner, "tedge");
+        assert_eq!(workflow.states["init"].next, vec!["rolled-back", "done"]);
+        assert_eq!(workflow.states["rolled-back"].owner, "external");
+        assert!(workflow.states["done"].next.is_empty());
+    }
+
+    #[test]
+    fn keep_the_fields_unknown_to_the_states() {
+        let operation =
+            OperationKey::try_from(&"tedge/operations/main-device/demo/update/1".to_string())
+                .unwrap();
+        let message = OperationPluginMessage {
+            operation: operation.clone(),
+            status: "init".to_string(),
+            json: serde_json::json!({
+                "status": "init",
+                "target": "demo",
+                "note": "added by a script",
+                "target_version": { "major": 1 },
+            }),
+        };
+        let state: DemoState = message_to_state(&message).unwrap();
+        let fields = unknown_fields(&message, &state);
+        assert_eq!(
+            fields.keys().collect::<Vec<_>>(),
+            vec!["note", "target_version"]
+        );
+
+        let request = DemoRequest {
+            target: "updated".to_string(),
+        };
+        let new_state = DemoState::Successful { request };
+        let message = state_to_message(operation, &new_state).unwrap();
+        let message = with_unknown_fields(message, &fields);
+        assert_eq!(
+            message.json,
+            serde_json::json!({
+                "status": "done",
+                "target": "updated",
+                "note": "added by a script",
+                "target_version": { "major": 1 },
+            })
+        );
+    }
+
+    fn operation(instance: &str) -> OperationKey {
+        OperationKey::try_from(&format!(
+            "tedge/operations/main-device/demo/update/{instance}"
+        ))
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn a_new_task_supersedes_the_previous_task_of_an_operation() {
+        let mut tasks: OperationPluginTasks<u32> = OperationPluginTasks::new();
+        let (sender, receiver) = tokio::sync::oneshot::channel::<()>();
+        tasks.spawn(&operation("1"), async move {
+            let _ = receiver.await;
+            Some(1)
+        });
+        tasks.spawn(&operation("1"), async { Some(2) });
+        tasks.spawn(&operation("2"), async { Some(3) });
+
+        let mut outcomes = vec![];
+        for _ in 0..2 {
+            let (operation, _, outcome) = tasks.next().await.unwrap();
+            outcomes.push((operation.instance, outcome.unwrap()));
+        }
+        outcomes.sort();
+        assert_eq!(
+            outcomes,
+            vec![("1".to_string(), Some(2)), ("2".to_string(), Some(3))]
+        );
+
+        // The first task has been aborted
+        assert!(sender.send(()).is_err());
+        assert!(!tasks.is_pending(&operation("1")));
+    }
+
+    #[tokio::test]
+    async fn discard_the_outcome_of_a_superseded_task() {
+        let mut tasks: OperationPluginTasks<u32> = OperationPluginTasks::new();
+        tasks.spawn(&operation("1"), async { Some(1) });
+        // Let the first task complete before being superseded
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+
+        let (sender, receiver) = tokio::sync::oneshot::channel::<()>();
+        tasks.spawn(&operation("1"), async move {
+            let _ = receiver.await;
+            Some(2)
+        });
+        let outcome =
+            tokio::time::timeout(std::time::Duration::from_millis(100), tasks.next()).await;
+        assert!(
+            outcome.is_err(),
+            "the outcome of the first task is discarded"
+        );
+        assert!(tasks.is_pending(&operation("1")));
+
+        sender.send(()).unwrap();
+        let (_, _, outcome) = tasks.next().await.unwrap();
+        assert_eq!(outcome, Ok(Some(2)));
+        assert!(!tasks.is_pending(&operation("1")));
+    }
+
+    /// A plugin that moves the operations to an intermediate state, while a background task panics
+    struct PanickingPlugin;
+
+    impl OperationPlugin for PanickingPlugin {
+        type State = DemoState;
+
+        const HANDLED_STATES: &'static [&'static str] = &["init"];
+
+        fn name(&self) -> &str {
+            "PanickingPlugin"
+        }
+
+        fn update(
+            &mut self,
+            operation: &OperationKey,
+            state: DemoState,
+            tasks: &mut OperationPluginTasks<DemoState>,
+        ) -> Option<DemoState> {
+            let DemoState::Init { request } = state else {
+                return None;
+            };
+            tasks.spawn(operation, async {
+                panic!("task failure");
+            });
+            Some(DemoState::RolledBack {
+                request,
+                reason: "in progress".to_string(),
+            })
+        }
+    }
+
+    fn init_event(instance: &str) -> (OperationPluginEvent, DemoState) {
+        let message = OperationPluginMessage {
+            operation: operation(instance),
+            status: "init".to_string(),
+            json: serde_json::json!({ "status": "init", "target": "demo" }),
+        };
+        let state = message_to_state(&message).unwrap();
+        (OperationPluginEvent::Update(message), state)
+    }
+
+    #[tokio::test]
+    async fn report_a_task_failure_against_the_state_left_by_its_step() {
+        let mut plugin = PanickingPlugin;
+        let mut tasks = OperationPluginTasks::new();
+        let (event, state) = init_event("1");
+
+        let new_state = run_step(&mut plugin, &mut tasks, &event, state).unwrap();
+        assert_eq!(new_state.map(|state| state.status()), Some("rolled-back"));
+
+        let (operation, status, outcome) = tasks.next().await.unwrap();
+        assert_eq!(operation.instance, "1");
+        assert_eq!(status, "rolled-back");
+        let reason = outcome.unwrap_err();
+        assert!(reason.contains("task failure"), "{reason}");
+    }
+
+    #[test]
+    fn the_plugins_handle_all_their_steps() {
+        assert_all_steps_handled::<ConfigManager>();
+        assert_all_steps_handled::<ConfigSnapshotManager>();
+        assert_all_steps_handled::<ConfigListManager>();
+        assert_all_steps_handled::<SoftwareManager>();
+        assert_all_steps_handled::<SoftwareListManager>();
+        assert_all_steps_handled::<DeviceManager>();
+        assert_all_steps_handled::<FirmwareManager>();
+        assert_all_steps_handled::<LogManager>();
+        assert_all_steps_handled::<CommandManager>();
+    }
+}
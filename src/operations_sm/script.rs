@@ -0,0 +1,38 @@
+use std::process::Output;
+use tedge_script_ext::Execute;
+
+/// Run a command, be it a workflow script, a plugin command or a remote command.
+///
+/// The process is killed if the returned future is dropped,
+/// e.g. when the task running the command is aborted on a cancellation.
+/// This is why the commands are not sent to the `ScriptActor`:
+/// a request sent to this actor can not be withdrawn, the process running until completion.
+pub async fn execute(command: &Execute) -> std::io::Result<Output> {
+    tokio::process::Command::new(&command.command)
+        .args(&command.args)
+        .kill_on_drop(true)
+        .output()
+        .await
+}
+
+/// Run a command line, returning its stdout on success and its stderr on failure
+pub async fn run_command(command_line: &str) -> Result<String, String> {
+    let command = Execute::try_new(command_line)
+        .map_err(|err| format!("Fail to parse the command line {command_line}: {err}"))?;
+    run(command_line, &command).await
+}
+
+/// Run a command, returning its stdout on success and its stderr on failure.
+///
+/// The command line is only used in the error messages.
+pub async fn run(command_line: &str, command: &Execute) -> Result<String, String> {
+    let output = execute(command)
+        .await
+        .map_err(|err| format!("Fail to launch {command_line}: {err}"))?;
+    if output.status.success() {
+        Ok(String::from_utf8_lossy(&output.stdout).to_string())
+    } else {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        Err(format!("{command_line} failed with: {stderr}"))
+    }
+}
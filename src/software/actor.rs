@@ -0,0 +1,265 @@
+use crate::operations_sm::config::OperationKey;
+use crate::operations_sm::plugin::{OperationPlugin, OperationPluginTasks};
+use crate::software::config::SoftwarePluginConfig;
+use crate::software::manager::apply;
+use crate::software::messages::{SoftwareUpdateRequest, SoftwareUpdateState};
+
+/// Plugin that handles the software update requests.
+pub struct SoftwareManager {
+    /// The package managers used by this plugin
+    config: SoftwarePluginConfig,
+}
+
+impl OperationPlugin for SoftwareManager {
+    type State = SoftwareUpdateState;
+
+    const HANDLED_STATES: &'static [&'static str] = &["init", "scheduled", "executing"];
+
+    fn name(&self) -> &str {
+        "SoftwareManager"
+    }
+
+    fn update(
+        &mut self,
+        operation: &OperationKey,
+        state: SoftwareUpdateState,
+        tasks: &mut OperationPluginTasks<SoftwareUpdateState>,
+    ) -> Option<SoftwareUpdateState> {
+        match state {
+            SoftwareUpdateState::Init { request } => Some(self.init(request)),
+            SoftwareUpdateState::Scheduled { request } => {
+                Some(self.start_execution(operation, request, tasks))
+            }
+            SoftwareUpdateState::Executing { .. } => {
+                // Nothing to do while this plugin awaits for the actual end of the execution
+                None
+            }
+            SoftwareUpdateState::Successful { .. }
+            | SoftwareUpdateState::Failed { .. }
+            | SoftwareUpdateState::Cancelled { .. } => None,
+        }
+    }
+
+    /// Resume an operation found on start in a state owned by this plugin.
+    ///
+    /// All the steps are simply processed again, except the execution
+    /// that has to be restarted when not known to be in progress.
+    /// This assumes the package managers can install or remove a module twice.
+    fn recover(
+        &mut self,
+        operation: &OperationKey,
+        state: SoftwareUpdateState,
+        tasks: &mut OperationPluginTasks<SoftwareUpdateState>,
+    ) -> Option<SoftwareUpdateState> {
+        match state {
+            SoftwareUpdateState::Executing { request } if !tasks.is_pending(operation) => {
+                log::info!(
+                    "Restart the interrupted software update for {}",
+                    String::from(operation)
+                );
+                self.start_execution(operation, request, tasks);
+                None
+            }
+            state => self.update(operation, state, tasks),
+        }
+    }
+}
+
+impl SoftwareManager {
+    pub fn new(config: SoftwarePluginConfig) -> Self {
+        SoftwareManager { config }
+    }
+
+    /// A new request is immediately scheduled, unless it uses an unknown package manager.
+    fn init(&mut self, request: SoftwareUpdateRequest) -> SoftwareUpdateState {
+        let unknown_type = request
+            .modules
+            .iter()
+            .find(|module| self.config.get_manager(&module.module_type).is_none());
+        match unknown_type {
+            None => SoftwareUpdateState::Scheduled { request },
+            Some(module) => {
+                let reason = format!("Unknown software type: {}", module.module_type);
+                SoftwareUpdateState::Failed { request, reason }
+            }
+        }
+    }
+
+    /// Install and remove the requested modules in the background, in order,
+    /// stopping on the first failure.
+    fn start_execution(
+        &mut self,
+        operation: &OperationKey,
+        request: SoftwareUpdateRequest,
+        tasks: &mut OperationPluginTasks<SoftwareUpdateState>,
+    ) -> SoftwareUpdateState {
+        let config = self.config.clone();
+        let task_request = request.clone();
+        tasks.spawn(operation, async move {
+            let request = task_request;
+            for module in request.modules.iter() {
+                let result = match config.get_manager(&module.module_type) {
+                    Some(manager) => apply(manager, module).await,
+                    None => Err(format!("Unknown software type: {}", module.module_type)),
+                };
+                if let Err(reason) = result {
+                    return Some(SoftwareUpdateState::Failed { request, reason });
+                }
+            }
+            Some(SoftwareUpdateState::Successful { request })
+        });
+        SoftwareUpdateState::Executing { request }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::software::config::PackageManagerConfig;
+    use crate::software::messages::{SoftwareAction, SoftwareModule};
+    use std::os::unix::fs::PermissionsExt;
+    use tempfile::TempDir;
+
+    /// A package manager logging its calls, and failing to install the `broken` module
+    fn stub_manager(dir: &TempDir) -> SoftwareManager {
+        let path = dir.path().join("apt.sh");
+        let script = format!(
+            "#!/bin/sh\necho \"$@\" >> {}\nif [ \"$2\" = broken ]; then echo 'broken: no such package' >&2; exit 1; fi\n",
+            dir.path().join("calls.log").display()
+        );
+        std::fs::write(&path, script).unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        SoftwareManager::new(SoftwarePluginConfig {
+            managers: vec![PackageManagerConfig {
+                manager_type: "apt".to_string(),
+                script: path.to_string_lossy().to_string(),
+                list_timeout: 1,
+            }],
+        })
+    }
+
+    fn calls(dir: &TempDir) -> String {
+        std::fs::read_to_string(dir.path().join("calls.log")).unwrap_or_default()
+    }
+
+    fn module(name: &str, version: Option<&str>, action: SoftwareAction) -> SoftwareModule {
+        SoftwareModule {
+            module_type: "apt".to_string(),
+            name: name.to_string(),
+            version: version.map(|version| version.to_string()),
+            action: Some(action),
+        }
+    }
+
+    fn operation() -> OperationKey {
+        OperationKey::try_from(&"tedge/operations/main-device/software/update/1".to_string())
+            .unwrap()
+    }
+
+    /// Run a request through the init, scheduled and executing steps
+    async fn update(
+        manager: &mut SoftwareManager,
+        modules: Vec<SoftwareModule>,
+    ) -> SoftwareUpdateState {
+        let mut tasks = OperationPluginTasks::new();
+        let request = SoftwareUpdateRequest { modules };
+
+        let init = SoftwareUpdateState::Init { request };
+        let scheduled = manager.update(&operation(), init, &mut tasks).unwrap();
+        assert!(matches!(scheduled, SoftwareUpdateState::Scheduled { .. }));
+        let executing = manager.update(&operation(), scheduled, &mut tasks).unwrap();
+        assert!(matches!(executing, SoftwareUpdateState::Executing { .. }));
+        assert_eq!(manager.update(&operation(), executing, &mut tasks), None);
+
+        let (_, _, outcome) = tasks.next().await.unwrap();
+        outcome.unwrap().unwrap()
+    }
+
+    #[tokio::test]
+    async fn install_and_remove_the_modules_in_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut manager = stub_manager(&dir);
+        let modules = vec![
+            module("curl", Some("7.88"), SoftwareAction::Install),
+            module("vim", None, SoftwareAction::Remove),
+            module("nano", None, SoftwareAction::Install),
+        ];
+
+        let outcome = update(&mut manager, modules.clone()).await;
+
+        assert_eq!(
+            outcome,
+            SoftwareUpdateState::Successful {
+                request: SoftwareUpdateRequest { modules }
+            }
+        );
+        assert_eq!(calls(&dir), "install curl 7.88\nremove vim\ninstall nano\n");
+    }
+
+    #[tokio::test]
+    async fn stop_on_the_first_module_that_fails() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut manager = stub_manager(&dir);
+        let modules = vec![
+            module("curl", None, SoftwareAction::Install),
+            module("broken", Some("1.0"), SoftwareAction::Install),
+            module("nano", None, SoftwareAction::Install),
+        ];
+
+        let outcome = update(&mut manager, modules).await;
+
+        let SoftwareUpdateState::Failed { reason, .. } = outcome else {
+            panic!("Unexpected state: {outcome:?}");
+        };
+        assert!(reason.contains("broken: no such package"), "{reason}");
+        assert_eq!(calls(&dir), "install curl\ninstall broken 1.0\n");
+    }
+
+    #[test]
+    fn fail_the_requests_for_an_unknown_software_type() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut manager = stub_manager(&dir);
+        let mut tasks = OperationPluginTasks::new();
+        let mut snap = module("hello", None, SoftwareAction::Install);
+        snap.module_type = "snap".to_string();
+        let request = SoftwareUpdateRequest {
+            modules: vec![module("curl", None, SoftwareAction::Install), snap],
+        };
+
+        let init = SoftwareUpdateState::Init {
+            request: request.clone(),
+        };
+        let outcome = manager.update(&operation(), init, &mut tasks);
+
+        assert_eq!(
+            outcome,
+            Some(SoftwareUpdateState::Failed {
+                request,
+                reason: "Unknown software type: snap".to_string(),
+            })
+        );
+        assert_eq!(calls(&dir), "");
+    }
+
+    #[tokio::test]
+    async fn restart_an_execution_interrupted_by_a_restart_of_the_daemon() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut manager = stub_manager(&dir);
+        let mut tasks = OperationPluginTasks::new();
+        let request = SoftwareUpdateRequest {
+            modules: vec![module("curl", None, SoftwareAction::Install)],
+        };
+
+        let executing = SoftwareUpdateState::Executing {
+            request: request.clone(),
+        };
+        assert_eq!(manager.recover(&operation(), executing, &mut tasks), None);
+
+        let (_, _, outcome) = tasks.next().await.unwrap();
+        assert_eq!(
+            outcome,
+            Ok(Some(SoftwareUpdateState::Successful { request }))
+        );
+        assert_eq!(calls(&dir), "install curl\n");
+    }
+}
@@ -0,0 +1,54 @@
+use crate::operations_sm::plugin::PluginConfig;
+use serde::{Deserialize, Serialize};
+
+/// The configuration of the software plugin
+///
+/// Lists the package managers used to install and remove software modules.
+///
+/// ```toml
+/// [[managers]]
+/// type = "apt"
+/// script = "./plugins/software/apt.sh"
+/// list_timeout = 60
+/// ```
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct SoftwarePluginConfig {
+    #[serde(default)]
+    pub managers: Vec<PackageManagerConfig>,
+}
+
+/// A package manager, implemented by a script
+///
+/// The script is called with the following sub-commands:
+/// - `install <name> [<version>]`
+/// - `remove <name> [<version>]`
+/// - `list`, printing on stdout one installed module per line, as `<name>\t<version>`.
+///
+/// The script signals a failure with a non-zero exit code, the reason being printed on stderr.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PackageManagerConfig {
+    /// The software type, as used by the requests to designate this package manager
+    #[serde(rename = "type")]
+    pub manager_type: String,
+
+    /// The path to the script implementing the package manager
+    pub script: String,
+
+    /// How long to wait, in seconds, for the list of the installed modules
+    #[serde(default = "default_list_timeout")]
+    pub list_timeout: u64,
+}
+
+fn default_list_timeout() -> u64 {
+    60
+}
+
+impl SoftwarePluginConfig {
+    pub fn get_manager(&self, manager_type: &str) -> Option<&PackageManagerConfig> {
+        self.managers
+            .iter()
+            .find(|manager| manager.manager_type == manager_type)
+    }
+}
+
+impl PluginConfig for SoftwarePluginConfig {}
@@ -0,0 +1,184 @@
+use crate::operations_sm::config::OperationKey;
+use crate::operations_sm::plugin::{OperationPlugin, OperationPluginTasks};
+use crate::software::config::SoftwarePluginConfig;
+use crate::software::list::messages::SoftwareListState;
+use crate::software::manager::list;
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+/// Plugin that handles the software list requests.
+pub struct SoftwareListManager {
+    /// The package managers queried by this plugin
+    config: SoftwarePluginConfig,
+}
+
+impl OperationPlugin for SoftwareListManager {
+    type State = SoftwareListState;
+
+    const HANDLED_STATES: &'static [&'static str] = &["init"];
+
+    fn name(&self) -> &str {
+        "SoftwareListManager"
+    }
+
+    fn update(
+        &mut self,
+        operation: &OperationKey,
+        state: SoftwareListState,
+        tasks: &mut OperationPluginTasks<SoftwareListState>,
+    ) -> Option<SoftwareListState> {
+        match state {
+            SoftwareListState::Init {} => {
+                self.start_listing(operation, tasks);
+                None
+            }
+            SoftwareListState::Successful { .. }
+            | SoftwareListState::Failed { .. }
+            | SoftwareListState::Cancelled { .. } => None,
+        }
+    }
+}
+
+impl SoftwareListManager {
+    pub fn new(config: SoftwarePluginConfig) -> Self {
+        SoftwareListManager { config }
+    }
+
+    /// Query all the package managers concurrently in the background.
+    ///
+    /// A package manager that fails or doesn't respond in time doesn't fail the whole operation:
+    /// the modules of the other managers are reported along an error for this manager.
+    /// The operation fails only if all the package managers fail.
+    fn start_listing(
+        &mut self,
+        operation: &OperationKey,
+        tasks: &mut OperationPluginTasks<SoftwareListState>,
+    ) {
+        let managers = self.config.managers.clone();
+        tasks.spawn(operation, async move {
+            let queries = managers.into_iter().map(|manager| async move {
+                let timeout = Duration::from_secs(manager.list_timeout);
+                let result = match tokio::time::timeout(timeout, list(&manager)).await {
+                    Ok(result) => result,
+                    Err(_) => Err(format!(
+                        "{} list timed out after {} seconds",
+                        manager.script, manager.list_timeout
+                    )),
+                };
+                (manager.manager_type, result)
+            });
+            let results = tedge_actors::futures::future::join_all(queries).await;
+
+            let count = results.len();
+            let mut modules = Vec::new();
+            let mut errors = BTreeMap::new();
+            for (manager_type, result) in results {
+                match result {
+                    Ok(mut manager_modules) => modules.append(&mut manager_modules),
+                    Err(reason) => {
+                        log::warn!("Fail to list the {manager_type} modules: {reason}");
+                        errors.insert(manager_type, reason);
+                    }
+                }
+            }
+
+            if count > 0 && errors.len() == count {
+                let reason = errors
+                    .into_iter()
+                    .map(|(manager_type, reason)| format!("{manager_type}: {reason}"))
+                    .collect::<Vec<_>>()
+                    .join("; ");
+                Some(SoftwareListState::Failed { reason })
+            } else {
+                Some(SoftwareListState::Successful { modules, errors })
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::software::config::PackageManagerConfig;
+    use crate::software::messages::SoftwareModule;
+    use std::os::unix::fs::PermissionsExt;
+    use tempfile::TempDir;
+
+    /// A package manager implemented by a stub script
+    fn stub_manager(dir: &TempDir, manager_type: &str, script: &str) -> PackageManagerConfig {
+        let path = dir.path().join(format!("{manager_type}.sh"));
+        std::fs::write(&path, format!("#!/bin/sh\n{script}\n")).unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        PackageManagerConfig {
+            manager_type: manager_type.to_string(),
+            script: path.to_string_lossy().to_string(),
+            list_timeout: 1,
+        }
+    }
+
+    fn module(module_type: &str, name: &str, version: &str) -> SoftwareModule {
+        SoftwareModule {
+            module_type: module_type.to_string(),
+            name: name.to_string(),
+            version: Some(version.to_string()),
+            action: None,
+        }
+    }
+
+    async fn list_modules(managers: Vec<PackageManagerConfig>) -> SoftwareListState {
+        let mut manager = SoftwareListManager::new(SoftwarePluginConfig { managers });
+        let mut tasks = OperationPluginTasks::new();
+        let operation =
+            OperationKey::try_from(&"tedge/operations/main-device/software/list/1".to_string())
+                .unwrap();
+
+        assert_eq!(
+            manager.update(&operation, SoftwareListState::Init {}, &mut tasks),
+            None
+        );
+        let (_, _, outcome) = tasks.next().await.unwrap();
+        outcome.unwrap().unwrap()
+    }
+
+    #[tokio::test]
+    async fn report_the_modules_of_the_managers_that_respond() {
+        let dir = tempfile::tempdir().unwrap();
+        let managers = vec![
+            stub_manager(&dir, "apt", r#"printf 'curl\t7.88\nvim\t9.0\n'"#),
+            stub_manager(&dir, "hanging", "sleep 10"),
+            stub_manager(&dir, "failing", "echo 'no database' >&2; exit 1"),
+        ];
+        let hanging = managers[1].script.clone();
+        let failing = managers[2].script.clone();
+
+        let SoftwareListState::Successful { modules, errors } = list_modules(managers).await else {
+            panic!("Expected the modules of the managers that respond");
+        };
+        assert_eq!(
+            modules,
+            vec![module("apt", "curl", "7.88"), module("apt", "vim", "9.0")]
+        );
+        assert_eq!(
+            errors.get("hanging"),
+            Some(&format!("{hanging} list timed out after 1 seconds"))
+        );
+        assert_eq!(
+            errors.get("failing"),
+            Some(&format!("{failing} list failed with: no database\n"))
+        );
+    }
+
+    #[tokio::test]
+    async fn fail_when_all_the_managers_fail() {
+        let dir = tempfile::tempdir().unwrap();
+        let managers = vec![
+            stub_manager(&dir, "hanging", "sleep 10"),
+            stub_manager(&dir, "failing", "exit 1"),
+        ];
+
+        assert!(matches!(
+            list_modules(managers).await,
+            SoftwareListState::Failed { .. }
+        ));
+    }
+}
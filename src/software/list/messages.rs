@@ -0,0 +1,36 @@
+use crate::software::messages::SoftwareModule;
+use serde::Deserialize;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use tedge_mqtt_state_machine_macros::OperationState;
+
+/// The states of a software list request
+///
+/// The default workflow of the operation is derived from the `next` attributes of the states.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize, OperationState)]
+#[serde(tag = "status", rename_all = "kebab-case")]
+#[operation(operation = "software", request = "list")]
+pub enum SoftwareListState {
+    #[operation(next = ["successful", "failed", "cancelled"])]
+    Init {},
+    /// The modules reported by the package managers that responded.
+    ///
+    /// The `errors` are indexed by the type of the package managers that failed or timed out.
+    #[operation(next = [])]
+    Successful {
+        #[serde(default)]
+        modules: Vec<SoftwareModule>,
+        #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+        errors: BTreeMap<String, String>,
+    },
+    #[operation(next = [])]
+    Failed {
+        #[serde(default)]
+        reason: String,
+    },
+    #[operation(next = [])]
+    Cancelled {
+        #[serde(default)]
+        reason: String,
+    },
+}
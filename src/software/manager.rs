@@ -0,0 +1,53 @@
+use crate::operations_sm::script::run;
+use crate::software::config::PackageManagerConfig;
+use crate::software::messages::{SoftwareAction, SoftwareModule};
+use tedge_script_ext::Execute;
+
+/// Install or remove a software module using its package manager
+pub async fn apply(manager: &PackageManagerConfig, module: &SoftwareModule) -> Result<(), String> {
+    let action = match module.action {
+        Some(SoftwareAction::Install) | None => "install",
+        Some(SoftwareAction::Remove) => "remove",
+    };
+    let mut args = vec![action, module.name.as_str()];
+    if let Some(version) = &module.version {
+        args.push(version.as_str());
+    }
+    run_script(manager, &args).await?;
+    Ok(())
+}
+
+/// List the software modules installed by a package manager
+pub async fn list(manager: &PackageManagerConfig) -> Result<Vec<SoftwareModule>, String> {
+    let output = run_script(manager, &["list"]).await?;
+    let modules = output
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| {
+            let mut fields = line.splitn(2, '\t');
+            let name = fields.next().unwrap_or_default().trim().to_string();
+            let version = fields
+                .next()
+                .map(|version| version.trim().to_string())
+                .filter(|version| !version.is_empty());
+            SoftwareModule {
+                module_type: manager.manager_type.clone(),
+                name,
+                version,
+                action: None,
+            }
+        })
+        .collect();
+    Ok(modules)
+}
+
+/// Run the package manager script, returning its stdout on success and its stderr on failure
+async fn run_script(manager: &PackageManagerConfig, args: &[&str]) -> Result<String, String> {
+    let script = &manager.script;
+    let command_line = format!("{script} {}", args.join(" "));
+    let command = Execute {
+        command: script.clone(),
+        args: args.iter().map(|arg| arg.to_string()).collect(),
+    };
+    run(&command_line, &command).await
+}
@@ -0,0 +1,80 @@
+use serde::Deserialize;
+use serde::Serialize;
+use tedge_mqtt_state_machine_macros::OperationState;
+
+/// A software module, as installed or to be installed by a package manager
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct SoftwareModule {
+    /// The software type, i.e. the package manager used to install this module
+    #[serde(rename = "type")]
+    pub module_type: String,
+
+    /// The module name
+    pub name: String,
+
+    /// The module version, if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+
+    /// What has to be done with this module, `install` being the default
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub action: Option<SoftwareAction>,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SoftwareAction {
+    Install,
+    Remove,
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct SoftwareUpdateRequest {
+    /// The modules to be installed or removed, in order
+    pub modules: Vec<SoftwareModule>,
+}
+
+/// The states of a software update request
+///
+/// The default workflow of the operation is derived from the `next` attributes of the states.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize, OperationState)]
+#[serde(tag = "status", rename_all = "kebab-case")]
+#[operation(operation = "software", request = "update")]
+pub enum SoftwareUpdateState {
+    /// The default behavior is to immediately schedule the new request.
+    #[operation(next = ["scheduled", "failed", "cancelled"])]
+    Init {
+        #[serde(flatten)]
+        request: SoftwareUpdateRequest,
+    },
+    #[operation(next = ["executing", "cancelled"])]
+    Scheduled {
+        #[serde(flatten)]
+        request: SoftwareUpdateRequest,
+    },
+    /// The installation and removal of the modules can not be cancelled.
+    #[operation(next = ["successful", "failed"])]
+    Executing {
+        #[serde(flatten)]
+        request: SoftwareUpdateRequest,
+    },
+    #[operation(next = [])]
+    Successful {
+        #[serde(flatten)]
+        request: SoftwareUpdateRequest,
+    },
+    #[operation(next = [])]
+    Failed {
+        #[serde(flatten)]
+        request: SoftwareUpdateRequest,
+        #[serde(default)]
+        reason: String,
+    },
+    #[operation(next = [])]
+    Cancelled {
+        #[serde(flatten)]
+        request: SoftwareUpdateRequest,
+        #[serde(default)]
+        reason: String,
+    },
+}
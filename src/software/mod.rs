@@ -0,0 +1,5 @@
+pub mod actor;
+pub mod config;
+pub mod list;
+pub mod manager;
+pub mod messages;